@@ -0,0 +1,211 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    iter,
+    path::{Path, PathBuf}
+};
+
+use image::{
+    AnimationDecoder,
+    Frame,
+    ImageFormat,
+    ImageError,
+    ImageResult,
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        png::PngDecoder
+    },
+    error::{EncodingError, ImageFormatHint}
+};
+
+
+// how hard the gif encoder tries to find good palettes, 1 is the slowest and best
+const GIF_SPEED: i32 = 10;
+
+// every frame of an animated gif or png, none if its a still image
+pub fn load_frames(path: &Path) -> ImageResult<Option<Vec<Frame>>>
+{
+    let format = ImageFormat::from_path(path).ok();
+
+    let reader = || File::open(path).map(BufReader::new);
+
+    let frames = match format
+    {
+        Some(ImageFormat::Gif) =>
+        {
+            GifDecoder::new(reader()?)?.into_frames().collect_frames()?
+        },
+        Some(ImageFormat::Png) =>
+        {
+            let decoder = PngDecoder::new(reader()?)?;
+
+            if !decoder.is_apng()
+            {
+                return Ok(None);
+            }
+
+            decoder.apng().into_frames().collect_frames()?
+        },
+        _ => return Ok(None)
+    };
+
+    Ok((frames.len() > 1).then_some(frames))
+}
+
+// every image in the directory, ordered by the numbers in their names
+pub fn numbered_frames(directory: &Path) -> io::Result<Vec<PathBuf>>
+{
+    let mut paths = fs::read_dir(directory)?.map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path|
+        {
+            path.as_ref().map(|path| path.is_file() && ImageFormat::from_path(path).is_ok())
+                .unwrap_or(true)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    paths.sort_by_cached_key(|path| natural_key(path));
+
+    Ok(paths)
+}
+
+// the file name split into text and the number after it, so frame10 comes after frame9
+fn natural_key(path: &Path) -> Vec<(String, u64)>
+{
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut chars = name.chars().peekable();
+
+    let mut key = Vec::new();
+    while chars.peek().is_some()
+    {
+        let text = iter::from_fn(|| chars.next_if(|c| !c.is_ascii_digit())).collect();
+        let number = iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect::<String>();
+
+        key.push((text, number.parse().unwrap_or(0)));
+    }
+
+    key
+}
+
+// whether save_frames can write to this path
+pub fn can_save(path: &Path) -> bool
+{
+    matches!(ImageFormat::from_path(path), Ok(ImageFormat::Gif | ImageFormat::Png))
+}
+
+// writes the frames as a looping gif or apng depending on the extension
+pub fn save_frames(path: &Path, frames: Vec<Frame>) -> ImageResult<()>
+{
+    let format = ImageFormat::from_path(path)?;
+
+    let writer = BufWriter::new(File::create(path)?);
+
+    match format
+    {
+        ImageFormat::Gif =>
+        {
+            let mut encoder = GifEncoder::new_with_speed(writer, GIF_SPEED);
+
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(frames)
+        },
+        ImageFormat::Png => save_apng(writer, frames).map_err(|err|
+        {
+            ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(ImageFormat::Png), err))
+        }),
+        format => Err(ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(format),
+            "animations can only be saved as gif or png"
+        )))
+    }
+}
+
+fn save_apng(writer: BufWriter<File>, frames: Vec<Frame>) -> Result<(), png::EncodingError>
+{
+    let (width, height) = frames.first().map(|frame| frame.buffer().dimensions())
+        .unwrap_or((1, 1));
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    // 0 plays means it loops forever
+    encoder.set_animated(frames.len() as u32, 0)?;
+
+    let mut writer = encoder.write_header()?;
+
+    for frame in frames
+    {
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+
+        // apng delays are in seconds, this keeps millisecond precision
+        let milliseconds = (numerator as f64 / denominator.max(1) as f64).round();
+
+        writer.set_frame_delay(milliseconds.min(u16::MAX as f64) as u16, 1000)?;
+        writer.write_image_data(frame.buffer().as_raw())?;
+    }
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use std::{env, process};
+
+    use image::{Rgba, RgbaImage, Delay};
+
+
+    #[test]
+    fn save_load()
+    {
+        let frames = || (0..3).map(|index|
+        {
+            let image = RgbaImage::from_pixel(4, 3, Rgba([index * 100, 50, 0, 255]));
+
+            Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(40 + index as u32 * 10, 1))
+        }).collect::<Vec<_>>();
+
+        ["gif", "png"].into_iter().for_each(|extension|
+        {
+            // the process id keeps test runs from writing over each others files
+            let name = format!("collager-animation-test-{}.{extension}", process::id());
+            let path = env::temp_dir().join(name);
+
+            save_frames(&path, frames()).unwrap();
+
+            let loaded = load_frames(&path).unwrap().expect("must be animated");
+
+            assert_eq!(loaded.len(), 3);
+
+            loaded.iter().zip(frames()).for_each(|(loaded, frame)|
+            {
+                assert_eq!(loaded.delay(), frame.delay());
+                assert_eq!(loaded.buffer().dimensions(), (4, 3));
+                assert_eq!(loaded.buffer().get_pixel(1, 1)[0], frame.buffer().get_pixel(1, 1)[0]);
+            });
+
+            std::fs::remove_file(path).unwrap();
+        });
+    }
+
+    #[test]
+    fn numbered_order()
+    {
+        let mut paths = ["frame10.png", "frame9.png", "frame1.png", "frame0010b.png", "a.png"]
+            .map(PathBuf::from);
+
+        paths.sort_by_cached_key(|path| natural_key(path));
+
+        let names = paths.iter().map(|path| path.to_str().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            ["a.png", "frame1.png", "frame9.png", "frame10.png", "frame0010b.png"]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+// min cost assignment of rows to columns where each column can take up to
+// capacity rows, costs are row major with columns entries per row
+// returns the column picked for each row, needs rows <= columns * capacity
+pub fn optimal(costs: &[f32], rows: usize, columns: usize, capacity: usize) -> Vec<usize>
+{
+    // a column cant take more rows than there are, so more slots would only cost time
+    let capacity = capacity.min(rows).max(1);
+
+    // every column gets repeated capacity times, slot j belongs to column j / capacity
+    let slots = columns * capacity;
+
+    assert!(rows <= slots, "not enough slots for every row");
+    assert_eq!(costs.len(), rows * columns);
+
+    let cost = |row: usize, slot: usize| -> f64
+    {
+        costs[(row - 1) * columns + (slot - 1) / capacity] as f64
+    };
+
+    // hungarian algorithm with potentials, everything is 1 indexed with 0 as a sentinel
+    let mut row_potential = vec![0.0_f64; rows + 1];
+    let mut slot_potential = vec![0.0_f64; slots + 1];
+
+    let mut slot_row = vec![0_usize; slots + 1];
+    let mut way = vec![0_usize; slots + 1];
+
+    for row in 1..=rows
+    {
+        slot_row[0] = row;
+
+        let mut current_slot = 0;
+
+        let mut min_values = vec![f64::INFINITY; slots + 1];
+        let mut used = vec![false; slots + 1];
+
+        loop
+        {
+            used[current_slot] = true;
+
+            let current_row = slot_row[current_slot];
+
+            let mut delta = f64::INFINITY;
+            let mut next_slot = 0;
+
+            for slot in 1..=slots
+            {
+                if used[slot]
+                {
+                    continue;
+                }
+
+                let reduced = cost(current_row, slot)
+                    - row_potential[current_row]
+                    - slot_potential[slot];
+
+                if reduced < min_values[slot]
+                {
+                    min_values[slot] = reduced;
+                    way[slot] = current_slot;
+                }
+
+                if min_values[slot] < delta
+                {
+                    delta = min_values[slot];
+                    next_slot = slot;
+                }
+            }
+
+            for slot in 0..=slots
+            {
+                if used[slot]
+                {
+                    row_potential[slot_row[slot]] += delta;
+                    slot_potential[slot] -= delta;
+                } else
+                {
+                    min_values[slot] -= delta;
+                }
+            }
+
+            current_slot = next_slot;
+
+            if slot_row[current_slot] == 0
+            {
+                break;
+            }
+        }
+
+        // flip the augmenting path
+        loop
+        {
+            let previous_slot = way[current_slot];
+
+            slot_row[current_slot] = slot_row[previous_slot];
+            current_slot = previous_slot;
+
+            if current_slot == 0
+            {
+                break;
+            }
+        }
+    }
+
+    let mut assigned = vec![0; rows];
+
+    slot_row.iter().enumerate().skip(1).filter(|(_, row)| **row != 0).for_each(|(slot, row)|
+    {
+        assigned[row - 1] = (slot - 1) / capacity;
+    });
+
+    assigned
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn total(costs: &[f32], columns: usize, assigned: &[usize]) -> f32
+    {
+        assigned.iter().enumerate().map(|(row, column)| costs[row * columns + column]).sum()
+    }
+
+    #[test]
+    fn square()
+    {
+        let costs = [
+            4.0, 1.0, 3.0,
+            2.0, 0.0, 5.0,
+            3.0, 2.0, 2.0
+        ];
+
+        let assigned = optimal(&costs, 3, 3, 1);
+
+        assert_eq!(assigned, vec![1, 0, 2]);
+        assert_eq!(total(&costs, 3, &assigned), 5.0);
+    }
+
+    #[test]
+    fn with_capacity()
+    {
+        // everyone wants the first column but it only fits two
+        let costs = [
+            1.0, 5.0, 9.0,
+            1.0, 6.0, 9.0,
+            1.0, 9.0, 7.0,
+            2.0, 3.0, 9.0
+        ];
+
+        let assigned = optimal(&costs, 4, 3, 2);
+
+        assert_eq!(total(&costs, 3, &assigned), 10.0);
+
+        // way more uses than rows acts the same as unlimited
+        let unlimited = optimal(&costs, 4, 3, 1_000_000_000);
+
+        assert_eq!(unlimited, vec![0, 0, 0, 0]);
+
+        (0..3).for_each(|column|
+        {
+            assert!(assigned.iter().filter(|x| **x == column).count() <= 2);
+        });
+    }
+}
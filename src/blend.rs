@@ -0,0 +1,214 @@
+use std::{
+    fmt,
+    array,
+    str::FromStr
+};
+
+use image::{Rgb, Rgba, RgbImage, RgbaImage};
+
+use crate::{
+    Vec2,
+    colors::{self, ColorSpace}
+};
+
+use serde::{Serialize, Deserialize};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlendMode
+{
+    Normal,
+    SoftLight,
+    Multiply,
+    // lightness from the target, colors from the collage
+    Luminosity
+}
+
+impl FromStr for BlendMode
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_lowercase().as_str()
+        {
+            "normal" => Ok(Self::Normal),
+            "soft-light" | "softlight" | "soft" => Ok(Self::SoftLight),
+            "multiply" => Ok(Self::Multiply),
+            "luminosity" => Ok(Self::Luminosity),
+            x => Err(format!(
+                "unknown blend mode: {x} (expected normal, soft-light, multiply or luminosity)"
+            ))
+        }
+    }
+}
+
+impl fmt::Display for BlendMode
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Self::Normal => "normal",
+            Self::SoftLight => "soft-light",
+            Self::Multiply => "multiply",
+            Self::Luminosity => "luminosity"
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl BlendMode
+{
+    // blends one channel of the target onto the backdrop, both from 0 to 1
+    fn channel(self, backdrop: f32, target: f32) -> f32
+    {
+        match self
+        {
+            Self::Normal => target,
+            Self::Multiply => backdrop * target,
+            Self::SoftLight =>
+            {
+                if target <= 0.5
+                {
+                    backdrop - (1.0 - 2.0 * target) * backdrop * (1.0 - backdrop)
+                } else
+                {
+                    let darkened = if backdrop <= 0.25
+                    {
+                        ((16.0 * backdrop - 12.0) * backdrop + 4.0) * backdrop
+                    } else
+                    {
+                        backdrop.sqrt()
+                    };
+
+                    backdrop + (2.0 * target - 1.0) * (darkened - backdrop)
+                }
+            },
+            Self::Luminosity => unreachable!("luminosity isnt separable")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Overlay
+{
+    // 0 keeps the collage, 1 is fully blended
+    pub opacity: f32,
+    pub mode: BlendMode,
+    // blend linear light values instead of srgb ones
+    pub linear: bool
+}
+
+impl Overlay
+{
+    // blends target on top of the collage starting at offset, transparent pixels stay as they are
+    pub fn apply(
+        &self,
+        collage: &mut RgbaImage,
+        target: &RgbImage,
+        offset: Vec2,
+        space: ColorSpace
+    )
+    {
+        let to_f32 = |value: u8| value as f32 / u8::MAX as f32;
+
+        target.enumerate_pixels().for_each(|(x, y, target)|
+        {
+            let Some(pixel) = collage.get_pixel_mut_checked(offset.x + x, offset.y + y)
+            else { return };
+
+            let Rgba([r, g, b, a]) = *pixel;
+
+            if a == 0
+            {
+                return;
+            }
+
+            let blended = self.blend([r, g, b].map(to_f32), target.0.map(to_f32), space);
+
+            let [r, g, b] = blended.map(|value|
+            {
+                (value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8
+            });
+
+            *pixel = Rgba([r, g, b, a]);
+        });
+    }
+
+    fn blend(&self, backdrop: [f32; 3], target: [f32; 3], space: ColorSpace) -> [f32; 3]
+    {
+        // lightness is already perceptual so luminosity always uses the srgb values
+        let linear = self.linear && self.mode != BlendMode::Luminosity;
+
+        let decode = |values: [f32; 3]|
+        {
+            if linear { values.map(colors::srgb_to_linear) } else { values }
+        };
+
+        let (backdrop, target) = (decode(backdrop), decode(target));
+
+        let blended = if self.mode == BlendMode::Luminosity
+        {
+            let backdrop_lab = space.from_rgb(Rgb::from(backdrop));
+            let target_lab = space.from_rgb(Rgb::from(target));
+
+            let lab = colors::Lab{l: target_lab.l, ..backdrop_lab};
+
+            space.to_rgb(lab).0.map(|value| value.clamp(0.0, 1.0))
+        } else
+        {
+            array::from_fn(|index| self.mode.channel(backdrop[index], target[index]))
+        };
+
+        let mixed: [f32; 3] = array::from_fn(|index|
+        {
+            backdrop[index] + (blended[index] - backdrop[index]) * self.opacity
+        });
+
+        if linear { mixed.map(colors::linear_to_srgb) } else { mixed }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn modes()
+    {
+        let overlay = |mode, opacity|
+        {
+            Overlay{opacity, mode, linear: false}
+        };
+
+        let backdrop = [0.2, 0.5, 0.8];
+        let target = [0.5, 0.5, 1.0];
+
+        let space = ColorSpace::Cielab;
+
+        assert_eq!(overlay(BlendMode::Normal, 1.0).blend(backdrop, target, space), target);
+        assert_eq!(overlay(BlendMode::Normal, 0.0).blend(backdrop, target, space), backdrop);
+
+        let halfway = overlay(BlendMode::Normal, 0.5).blend(backdrop, target, space);
+        assert!((halfway[0] - 0.35).abs() < 0.0001);
+
+        let multiplied = overlay(BlendMode::Multiply, 1.0).blend(backdrop, target, space);
+        assert!((multiplied[0] - 0.1).abs() < 0.0001 && multiplied[2] == 0.8);
+
+        // soft light with middle grey does nothing
+        let soft = overlay(BlendMode::SoftLight, 1.0).blend(backdrop, [0.5; 3], space);
+        backdrop.iter().zip(soft).for_each(|(a, b)| assert!((a - b).abs() < 0.0001));
+
+        // grey target onto a grey backdrop only changes how light it is
+        let luminosity = overlay(BlendMode::Luminosity, 1.0).blend([0.2; 3], [0.7; 3], space);
+        luminosity.iter().for_each(|value| assert!((value - 0.7).abs() < 0.01));
+
+        let linear = Overlay{opacity: 0.5, mode: BlendMode::Normal, linear: true};
+        let mixed = linear.blend([0.0; 3], [1.0; 3], space);
+        assert!((mixed[0] - colors::linear_to_srgb(0.5)).abs() < 0.0001);
+    }
+}
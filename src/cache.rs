@@ -0,0 +1,365 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+    collections::HashMap
+};
+
+use image::RgbImage;
+
+use crate::{
+    LabImage,
+    colors::ColorSpace
+};
+
+
+const MAGIC: &[u8] = b"COLLAGERCACHE";
+const VERSION: u32 = 2;
+
+// everything that changes what tiles a file turns into
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey
+{
+    pub path: PathBuf,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub allow_rotate: bool,
+    pub allow_invert: bool,
+    pub space: ColorSpace
+}
+
+// if the file changes so does this
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp
+{
+    pub modified_seconds: u64,
+    pub modified_nanos: u32,
+    pub size: u64
+}
+
+impl FileStamp
+{
+    pub fn new(path: &Path) -> io::Result<Self>
+    {
+        let metadata = fs::metadata(path)?;
+
+        let modified = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(Self{
+            modified_seconds: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            size: metadata.len()
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry
+{
+    pub stamp: FileStamp,
+    pub tiles: Vec<(RgbImage, LabImage)>
+}
+
+// resized tiles and their lab versions, so the library doesnt have to be decoded every run
+pub struct TileCache
+{
+    path: PathBuf,
+    entries: HashMap<CacheKey, CacheEntry>
+}
+
+impl TileCache
+{
+    // a missing or broken cache file just starts an empty cache
+    pub fn load(path: PathBuf) -> Self
+    {
+        let entries = File::open(&path).ok().and_then(|file|
+        {
+            Self::read_entries(&mut BufReader::new(file)).ok()
+        }).unwrap_or_default();
+
+        Self{path, entries}
+    }
+
+    // the default cache file sits next to the directory, so . gets its real name first
+    pub fn default_path(directory: &Path) -> PathBuf
+    {
+        let directory = fs::canonicalize(directory).unwrap_or_else(|_| directory.to_owned());
+
+        let name = directory.file_name().map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "library".to_owned());
+
+        directory.with_file_name(format!("{name}.collager-cache"))
+    }
+
+    pub fn get(&self, key: &CacheKey, stamp: FileStamp) -> Option<&CacheEntry>
+    {
+        self.entries.get(key).filter(|entry| entry.stamp == stamp)
+    }
+
+    pub fn insert(&mut self, key: CacheKey, entry: CacheEntry)
+    {
+        self.entries.insert(key, entry);
+    }
+
+    // forgets files from this directory that arent there anymore
+    pub fn retain_directory(&mut self, directory: &Path, paths: &[PathBuf])
+    {
+        self.entries.retain(|key, _|
+        {
+            key.path.parent() != Some(directory) || paths.contains(&key.path)
+        });
+    }
+
+    pub fn save(&self) -> io::Result<()>
+    {
+        let temporary = self.path.with_extension("collager-cache-tmp");
+
+        {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+
+            self.write_entries(&mut writer)?;
+
+            writer.flush()?;
+        }
+
+        fs::rename(temporary, &self.path)
+    }
+
+    fn write_entries(&self, writer: &mut impl Write) -> io::Result<()>
+    {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+
+        write_u64(writer, self.entries.len() as u64)?;
+
+        for (key, entry) in self.entries.iter()
+        {
+            let path = key.path.to_string_lossy();
+
+            write_u64(writer, path.len() as u64)?;
+            writer.write_all(path.as_bytes())?;
+
+            write_u32(writer, key.tile_width)?;
+            write_u32(writer, key.tile_height)?;
+
+            let space = match key.space
+            {
+                ColorSpace::Cielab => 0,
+                ColorSpace::Oklab => 1
+            };
+
+            writer.write_all(&[key.allow_rotate as u8, key.allow_invert as u8, space])?;
+
+            write_u64(writer, entry.stamp.modified_seconds)?;
+            write_u32(writer, entry.stamp.modified_nanos)?;
+            write_u64(writer, entry.stamp.size)?;
+
+            write_u32(writer, entry.tiles.len() as u32)?;
+
+            for (rgb, lab) in entry.tiles.iter()
+            {
+                write_u32(writer, rgb.width())?;
+                write_u32(writer, rgb.height())?;
+
+                writer.write_all(rgb.as_raw())?;
+
+                for value in lab.lab_values()
+                {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_entries(reader: &mut impl Read) -> io::Result<HashMap<CacheKey, CacheEntry>>
+    {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid cache file");
+
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+
+        if magic != MAGIC || read_u32(reader)? != VERSION
+        {
+            return Err(invalid());
+        }
+
+        let amount = read_u64(reader)?;
+
+        (0..amount).map(|_|
+        {
+            let path_length = read_u64(reader)?;
+
+            let path = read_bytes(reader, path_length)?;
+
+            let path = PathBuf::from(String::from_utf8(path).map_err(|_| invalid())?);
+
+            let tile_width = read_u32(reader)?;
+            let tile_height = read_u32(reader)?;
+
+            let mut flags = [0; 3];
+            reader.read_exact(&mut flags)?;
+
+            let space = match flags[2]
+            {
+                0 => ColorSpace::Cielab,
+                1 => ColorSpace::Oklab,
+                _ => return Err(invalid())
+            };
+
+            let key = CacheKey{
+                path,
+                tile_width,
+                tile_height,
+                allow_rotate: flags[0] != 0,
+                allow_invert: flags[1] != 0,
+                space
+            };
+
+            let stamp = FileStamp{
+                modified_seconds: read_u64(reader)?,
+                modified_nanos: read_u32(reader)?,
+                size: read_u64(reader)?
+            };
+
+            let tiles_amount = read_u32(reader)?;
+
+            let tiles = (0..tiles_amount).map(|_|
+            {
+                let width = read_u32(reader)?;
+                let height = read_u32(reader)?;
+
+                let values_amount = (width as u64).checked_mul(height as u64)
+                    .and_then(|pixels| pixels.checked_mul(3))
+                    .ok_or_else(invalid)?;
+
+                let rgb = read_bytes(reader, values_amount)?;
+
+                let rgb = RgbImage::from_raw(width, height, rgb).ok_or_else(invalid)?;
+
+                let lab = read_bytes(reader, values_amount * 4)?.chunks_exact(4).map(|bytes|
+                {
+                    f32::from_le_bytes(bytes.try_into().expect("chunks must be 4 bytes"))
+                }).collect::<Vec<_>>();
+
+                let lab = LabImage::from_lab_values(width, height, lab, space)
+                    .ok_or_else(invalid)?;
+
+                Ok((rgb, lab))
+            }).collect::<io::Result<Vec<_>>>()?;
+
+            Ok((key, CacheEntry{stamp, tiles}))
+        }).collect()
+    }
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()>
+{
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()>
+{
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32>
+{
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64>
+{
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// the buffer only grows as far as the file goes, so a broken length cant allocate everything
+fn read_bytes(reader: &mut impl Read, length: u64) -> io::Result<Vec<u8>>
+{
+    let mut bytes = Vec::new();
+    reader.by_ref().take(length).read_to_end(&mut bytes)?;
+
+    if bytes.len() as u64 != length
+    {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "cache file ended early"));
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use std::env;
+
+    use image::Rgb;
+
+
+    #[test]
+    fn write_read()
+    {
+        let rgb = RgbImage::from_fn(3, 2, |x, y| Rgb::from([x as u8 * 50, y as u8 * 90, 7]));
+        let lab = LabImage::from_rgb(rgb.clone(), ColorSpace::Oklab);
+
+        let key = CacheKey{
+            path: PathBuf::from("some/image.png"),
+            tile_width: 3,
+            tile_height: 2,
+            allow_rotate: true,
+            allow_invert: false,
+            space: ColorSpace::Oklab
+        };
+
+        let stamp = FileStamp{modified_seconds: 12345, modified_nanos: 6789, size: 1000};
+
+        let mut cache = TileCache{path: PathBuf::new(), entries: HashMap::new()};
+        cache.insert(key.clone(), CacheEntry{stamp, tiles: vec![(rgb.clone(), lab.clone())]});
+
+        let mut bytes = Vec::new();
+        cache.write_entries(&mut bytes).unwrap();
+
+        let entries = TileCache::read_entries(&mut bytes.as_slice()).unwrap();
+
+        let cache = TileCache{path: PathBuf::new(), entries};
+
+        let changed = FileStamp{size: 1001, ..stamp};
+        assert!(cache.get(&key, changed).is_none());
+
+        let entry = cache.get(&key, stamp).unwrap();
+
+        assert_eq!(entry.tiles.len(), 1);
+        assert_eq!(entry.tiles[0].0, rgb);
+        assert_eq!(entry.tiles[0].1.lab_values(), lab.lab_values());
+
+        assert!(TileCache::read_entries(&mut &bytes[1..]).is_err());
+
+        // a huge path length in a short file
+        let mut broken = bytes[..MAGIC.len() + 12].to_vec();
+        broken.extend(u64::MAX.to_le_bytes());
+
+        assert!(TileCache::read_entries(&mut broken.as_slice()).is_err());
+    }
+
+    #[test]
+    fn default_path()
+    {
+        let path = TileCache::default_path(Path::new("some/library/"));
+        assert_eq!(path, PathBuf::from("some/library.collager-cache"));
+
+        // the current directory isnt named . so the cache doesnt end up inside it
+        let path = TileCache::default_path(Path::new("."));
+        assert_eq!(path.parent(), env::current_dir().unwrap().parent());
+    }
+}
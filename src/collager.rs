@@ -1,280 +1,2510 @@
 use std::{
-    fs,
-    thread,
+    fmt,
+    str::FromStr,
     path::PathBuf,
+    collections::{HashMap, HashSet},
     sync::Arc,
     ops::ControlFlow
 };
 
 use image::{
     Rgb,
+    Rgba,
+    Pixel,
     RgbImage,
+    RgbaImage,
+    DynamicImage,
+    Rgb32FImage,
+    buffer::ConvertBuffer,
     ImageBuffer,
     imageops::{self, FilterType}
 };
 
+use serde::{Serialize, Deserialize};
+
 use crate::{
     Lab,
     LabImage,
-    imager::{LabImagesContainer, ImagesContainer}
+    colors::{self, DistanceMetric, ColorSpace},
+    imager::{self, Imager, LabImagesContainer, ImagesContainer, Pyramid, TileSource},
+    index::{self, TileIndex},
+    pool::WorkerPool,
+    grid::Grid,
+    blend::Overlay,
+    manifest::{Manifest, ManifestCell, ManifestLayer},
+    assignment
 };
 
 
 const SQRT_DISTANCE: bool = false;
 
-pub struct Vec2
+#[derive(Debug, Clone, Copy)]
+pub enum Search
 {
-    pub x: u32,
-    pub y: u32
+    Linear,
+    // compares only this many closest tiles by their descriptors if its set,
+    // otherwise keeps going until the rest cant possibly be better
+    Indexed{candidates: Option<usize>}
 }
 
-pub struct Collager
+// columns when no size is given at all
+const DEFAULT_WIDTH: u32 = 16;
+
+// how far the diffused error can push a cells colors, so it doesnt run away
+// when nothing in the library can get close
+const DIFFUSION_LIMIT: f32 = 25.0;
+
+// library images per thread before the diffusion search of a cell gets split up
+const DIFFUSION_CHUNK: usize = 256;
+
+// how many of the best tiles get remembered for each cell when limiting reuse
+const REUSE_SHORTLIST: usize = 16;
+
+// how many of the best tiles each cell considers in the approximate assignment
+const APPROXIMATE_SHORTLIST: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment
 {
-    image: LabImage,
-    width: u32,
-    height: u32,
-    pixel_size: u32,
-    output_indices: Option<PathBuf>
+    // each cell takes the best tile it can in order
+    Greedy,
+    // min cost assignment over every cell and tile
+    Optimal,
+    // every cell only considers its few best tiles, then the cheapest pairs
+    // get assigned first and whatever cells are left get the best tile that
+    // still has uses left
+    Approximate
 }
 
-impl Collager
+impl FromStr for Assignment
 {
-    pub fn new(
-        image: RgbImage,
-        width: u32,
-        pixel_size: u32,
-        output_indices: Option<PathBuf>
-    ) -> Self
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
     {
-        let total_width = width * pixel_size;
-        let width_scale = total_width as f64 / image.width() as f64;
+        match s.to_lowercase().as_str()
+        {
+            "greedy" => Ok(Self::Greedy),
+            "optimal" => Ok(Self::Optimal),
+            "approximate" | "approx" => Ok(Self::Approximate),
+            x => Err(format!("unknown assignment: {x} (expected greedy, optimal or approximate)"))
+        }
+    }
+}
 
-        let total_height = (image.height() as f64 * width_scale).ceil() as u32;
+impl fmt::Display for Assignment
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Self::Greedy => "greedy",
+            Self::Optimal => "optimal",
+            Self::Approximate => "approximate"
+        };
 
-        let filter_type = FilterType::CatmullRom;
+        write!(f, "{name}")
+    }
+}
 
-        let image: LabImage = imageops::resize(&image, total_width, total_height, filter_type)
-            .into();
+// what happens when the target doesnt have the same aspect ratio as the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FitMode
+{
+    // the grid gets smaller until it matches the target
+    Fit,
+    // the target gets scaled to cover the grid and the rest is cropped off
+    Fill,
+    // the target gets scaled to fit in the grid and the rest is background
+    Pad
+}
 
-        let height = total_height / pixel_size;
+impl FromStr for FitMode
+{
+    type Err = String;
 
-        Self{image, width, height, pixel_size, output_indices}
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_lowercase().as_str()
+        {
+            "fit" => Ok(Self::Fit),
+            "fill" | "crop" => Ok(Self::Fill),
+            "pad" => Ok(Self::Pad),
+            x => Err(format!("unknown fit mode: {x} (expected fit, fill or pad)"))
+        }
     }
+}
 
-    pub fn collage(&self, images: Arc<ImagesContainer>) -> RgbImage
+impl fmt::Display for FitMode
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
     {
-        let lab_images: LabImagesContainer = images.iter().cloned().map(|pair|
+        let name = match self
         {
-            LabImage::from(pair.image)
-        }).collect();
+            Self::Fit => "fit",
+            Self::Fill => "fill",
+            Self::Pad => "pad"
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReuseLimits
+{
+    pub max_uses: Option<usize>,
+    // in cells
+    pub min_distance: Option<f32>
+}
 
-        let indices = self.best_indices(Arc::new(lab_images));
+struct ReuseTracker
+{
+    limits: ReuseLimits,
+    placed: HashMap<usize, Vec<Vec2>>
+}
 
-        self.construct_from_indices(indices.into_iter(), &images)
+impl ReuseTracker
+{
+    fn new(limits: ReuseLimits) -> Self
+    {
+        Self{limits, placed: HashMap::new()}
     }
 
-    fn positions_iter(&self) -> impl Iterator<Item=Vec2> + '_
+    fn allowed(&self, source: usize, cell: Vec2) -> bool
     {
-        (0..self.height).flat_map(move |y|
+        let placed = if let Some(placed) = self.placed.get(&source)
         {
-            (0..self.width).map(move |x|
+            placed
+        } else
+        {
+            return true;
+        };
+
+        let under_uses = self.limits.max_uses.map(|max_uses| placed.len() < max_uses)
+            .unwrap_or(true);
+
+        let far_enough = self.limits.min_distance.map(|min_distance|
+        {
+            placed.iter().all(|other|
             {
-                Vec2{x: x * self.pixel_size, y: y * self.pixel_size}
+                let x = cell.x as f32 - other.x as f32;
+                let y = cell.y as f32 - other.y as f32;
+
+                x.hypot(y) >= min_distance
             })
-        })
+        }).unwrap_or(true);
+
+        under_uses && far_enough
     }
 
-    fn construct_from_indices(
-        &self,
-        indices: impl Iterator<Item=usize> + Clone,
-        images: &ImagesContainer
-    ) -> RgbImage
+    fn add(&mut self, source: usize, cell: Vec2)
     {
-        if let Some(path) = self.output_indices.as_ref()
-        {
-            let mut s = String::new();
+        self.placed.entry(source).or_default().push(cell);
+    }
+}
 
-            let mut indices = indices.clone();
-            for y in 0..self.height
-            {
-                for x in 0..self.width
-                {
-                    let index = indices.next()
-                        .expect("width * height must be equal to amount of indices");
+// libraries built once with Collager::libraries
+pub struct Libraries(Vec<Library>);
+
+struct Library
+{
+    images: Arc<LabImagesContainer>,
+    // the images at this librarys size, none for the full size ones the imager has
+    rgb: Option<Vec<RgbImage>>,
+    index: Option<TileIndex>,
+    // transformed copies of the same image share the source
+    sources: Vec<usize>,
+    sources_amount: usize
+}
 
-                    s += &images[index].name;
+#[derive(Debug, Clone, Copy)]
+struct Fit
+{
+    index: usize,
+    error: f32
+}
 
-                    // if not last
-                    if x != (self.width - 1)
-                    {
-                        s.push(' ');
-                    }
-                }
+// bound is the lowest error this candidate can possibly have
+struct Candidate<'a>
+{
+    index: usize,
+    image: &'a LabImage,
+    bound: f32
+}
 
-                // if not last
-                if y != (self.height - 1)
-                {
-                    s.push('\n');
-                }
-            }
+// where a tile goes, in the target and in the output
+#[derive(Debug, Clone, Copy)]
+struct Placement
+{
+    // the grid cell its in
+    cell: Vec2,
+    position: Vec2,
+    size: Vec2,
+    output: Vec2,
+    output_size: Vec2,
+    // which library the tile is from
+    level: usize,
+    index: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Vec2
+{
+    pub x: u32,
+    pub y: u32
+}
+
+// spacing around the tiles in output pixels
+#[derive(Debug, Clone, Copy)]
+pub struct Grout
+{
+    pub gap: u32,
+    pub margin: u32,
+    // of the tile corners
+    pub radius: u32,
+    // none leaves the spacing transparent
+    pub color: Option<Rgb<u8>>
+}
+
+impl Grout
+{
+    // whether the rounded corners leave this pixel of a tile
+    pub fn covers(&self, x: u32, y: u32, size: Vec2) -> bool
+    {
+        let radius = self.radius.min(size.x / 2).min(size.y / 2) as f32;
 
-            // if it crashes then its over
-            fs::write(path, s).unwrap();
+        if radius == 0.0
+        {
+            return true;
         }
 
-        let mut image =
+        let x = x as f32 + 0.5;
+        let y = y as f32 + 0.5;
+
+        let closest_x = x.clamp(radius, size.x as f32 - radius);
+        let closest_y = y.clamp(radius, size.y as f32 - radius);
+
+        (x - closest_x).hypot(y - closest_y) <= radius
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ColorCorrection
+{
+    // 0 leaves the tile untouched, 1 moves it fully onto the target cell
+    pub strength: f32,
+    pub match_deviation: bool
+}
+
+// cells get split into four while their best tile is worse than the threshold
+#[derive(Debug, Clone, Copy)]
+pub struct Quadtree
+{
+    pub min_size: u32,
+    // root mean square distance per pixel
+    pub threshold: f32
+}
+
+#[derive(Clone)]
+pub struct Config
+{
+    // in cells, anything not set gets picked to keep the targets aspect ratio
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    // roughly how many cells there should be if neither width nor height are set
+    pub tiles_total: Option<u32>,
+    pub fit: FitMode,
+    pub background: Rgb<u8>,
+    pub grout: Grout,
+    pub tile_size: Vec2,
+    pub grid: Grid,
+    pub metric: DistanceMetric,
+    pub space: ColorSpace,
+    pub search: Search,
+    pub reuse: Option<ReuseLimits>,
+    pub assignment: Assignment,
+    pub correction: Option<ColorCorrection>,
+    pub render_size: Option<Vec2>,
+    pub quadtree: Option<Quadtree>,
+    pub overlay: Option<Overlay>,
+    // strength of spreading each cells color error onto its neighbours
+    pub diffusion: Option<f32>,
+    // error per pixel added to every tile except the one the cell had in the previous frame
+    pub switch_cost: Option<f32>,
+    pub pool: WorkerPool,
+    // whether to describe every cell in a manifest and the path of the target to put in it
+    pub manifest: bool,
+    pub target_path: Option<PathBuf>
+}
+
+pub struct Collage
+{
+    pub image: DynamicImage,
+    // which library image went into each cell
+    pub indices: Vec<usize>,
+    pub manifest: Option<Manifest>
+}
+
+pub struct Collager
+{
+    image: LabImage,
+    width: u32,
+    height: u32,
+    // the part of the target image that isnt padding
+    content: Option<(Vec2, Vec2)>,
+    fit: FitMode,
+    background: Rgb<u8>,
+    grout: Grout,
+    tile_size: Vec2,
+    grid: Grid,
+    // which pixels of a cell get matched and drawn
+    mask: Option<Vec<bool>>,
+    metric: DistanceMetric,
+    space: ColorSpace,
+    search: Search,
+    reuse: Option<ReuseLimits>,
+    assignment: Assignment,
+    correction: Option<ColorCorrection>,
+    render_size: Option<Vec2>,
+    quadtree: Option<Quadtree>,
+    // the target framed at the output size to blend on top
+    overlay: Option<(Overlay, RgbImage)>,
+    diffusion: Option<f32>,
+    switch_cost: Option<f32>,
+    // which tile each cell position had in the previous frame
+    previous: HashMap<Vec2, usize>,
+    pool: WorkerPool,
+    manifest: bool,
+    target_path: Option<PathBuf>
+}
+
+impl Collager
+{
+    pub fn new(image: RgbImage, config: Config) -> Self
+    {
+        let Config{
+            width,
+            height,
+            tiles_total,
+            fit,
+            background,
+            grout,
+            tile_size,
+            grid,
+            metric,
+            space,
+            search,
+            reuse,
+            assignment,
+            correction,
+            render_size,
+            quadtree,
+            overlay,
+            diffusion,
+            switch_cost,
+            pool,
+            manifest,
+            target_path
+        } = config;
+
+        let image_size = Vec2{x: image.width(), y: image.height()};
+
+        let (width, height) = Self::grid_cells(
+            grid,
+            tile_size,
+            image_size,
+            (width, height, tiles_total),
+            fit
+        );
+
+        let overlay = overlay.map(|overlay|
         {
-            let pixel = Rgb::<u8>::from([0, 0, 0]);
+            let output_tile = render_size.unwrap_or(tile_size);
+            let pitch = Vec2{x: output_tile.x + grout.gap, y: output_tile.y + grout.gap};
+
+            let total = grid.size(width, height, pitch);
+            let total = Vec2{
+                x: total.x.saturating_sub(grout.gap).max(1),
+                y: total.y.saturating_sub(grout.gap).max(1)
+            };
+
+            (overlay, Self::framed_target(image.clone(), total, fit, background).0)
+        });
+
+        let total = grid.size(width, height, tile_size);
+
+        let (image, content) = Self::framed_target(image, total, fit, background);
 
-            let width = self.width * self.pixel_size;
-            let height = self.height * self.pixel_size;
+        Self{
+            image: LabImage::from_rgb(image, space),
+            width,
+            height,
+            content,
+            fit,
+            background,
+            grout,
+            tile_size,
+            grid,
+            mask: grid.mask(tile_size),
+            metric,
+            space,
+            search,
+            reuse,
+            assignment,
+            correction,
+            render_size,
+            quadtree,
+            overlay,
+            diffusion,
+            switch_cost,
+            previous: HashMap::new(),
+            pool,
+            manifest,
+            target_path
+        }
+    }
+
+    // amount of columns and rows, rounded to the closest amount that keeps the aspect ratio
+    fn grid_cells(
+        grid: Grid,
+        tile_size: Vec2,
+        image_size: Vec2,
+        (width, height, tiles_total): (Option<u32>, Option<u32>, Option<u32>),
+        fit: FitMode
+    ) -> (u32, u32)
+    {
+        let aspect = image_size.y as f64 / image_size.x.max(1) as f64;
 
-            ImageBuffer::from_pixel(width, height, pixel)
+        let rows_for = |columns: u32|
+        {
+            let total_width = grid.width(columns, tile_size) as f64;
+
+            grid.rows((total_width * aspect).round() as u32, tile_size)
         };
 
-        self.positions_iter().zip(indices).for_each(|(position, index)|
+        let columns_for = |rows: u32|
         {
-            let mut copy_pixel = |x, y|
-            {
-                let pixel = images[index].image.get_pixel(x, y);
+            let total_height = grid.size(1, rows, tile_size).y as f64;
 
-                image.put_pixel(position.x + x, position.y + y, *pixel);
-            };
+            grid.columns((total_height / aspect).round() as u32, tile_size)
+        };
 
-            for y in 0..self.pixel_size
+        match (width, height, tiles_total)
+        {
+            (Some(width), Some(height), _) =>
             {
-                for x in 0..self.pixel_size
+                if fit != FitMode::Fit
                 {
-                    copy_pixel(x, y);
+                    return (width, height);
                 }
-            }
-        });
 
-        image
+                let rows = rows_for(width);
+                if rows <= height
+                {
+                    (width, rows)
+                } else
+                {
+                    (columns_for(height).min(width), height)
+                }
+            },
+            (Some(width), None, _) => (width, rows_for(width)),
+            (None, Some(height), _) => (columns_for(height), height),
+            (None, None, Some(total)) =>
+            {
+                (1..=total.max(1)).map(|columns| (columns, rows_for(columns)))
+                    .min_by_key(|(columns, rows)| (columns * rows).abs_diff(total))
+                    .expect("there must be at least one column")
+            },
+            (None, None, None) => (DEFAULT_WIDTH, rows_for(DEFAULT_WIDTH))
+        }
     }
 
-    fn best_indices(&self, images: Arc<LabImagesContainer>) -> Vec<usize>
+    // the target scaled to exactly total pixels and where the actual image is if its padded
+    fn framed_target(
+        image: RgbImage,
+        total: Vec2,
+        fit: FitMode,
+        background: Rgb<u8>
+    ) -> (RgbImage, Option<(Vec2, Vec2)>)
     {
-        let handles = self.positions_iter().map(move |position|
+        let filter_type = FilterType::CatmullRom;
+
+        let scale_x = total.x as f64 / image.width() as f64;
+        let scale_y = total.y as f64 / image.height() as f64;
+
+        let scaled = |scale: f64|
         {
-            let image = self.image.clone();
-            let images = images.clone();
+            let width = ((image.width() as f64 * scale).round() as u32).max(1);
+            let height = ((image.height() as f64 * scale).round() as u32).max(1);
 
-            let size = Vec2{x: self.pixel_size, y: self.pixel_size};
+            imageops::resize(&image, width, height, filter_type)
+        };
 
-            thread::spawn(move ||
+        match fit
+        {
+            FitMode::Fit => (imageops::resize(&image, total.x, total.y, filter_type), None),
+            FitMode::Fill =>
             {
-                Self::best_fit_index_assoc(
-                    &image,
-                    images.iter(),
-                    position,
-                    size
-                )
-            })
-        }).collect::<Vec<_>>();
+                let scaled = scaled(scale_x.max(scale_y));
+
+                let x = scaled.width().saturating_sub(total.x) / 2;
+                let y = scaled.height().saturating_sub(total.y) / 2;
+
+                let cropped = imageops::crop_imm(&scaled, x, y, total.x, total.y).to_image();
+
+                // rounding can leave the scaled image a pixel short
+                (imageops::resize(&cropped, total.x, total.y, filter_type), None)
+            },
+            FitMode::Pad =>
+            {
+                let scaled = scaled(scale_x.min(scale_y));
 
-        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+                let size = Vec2{
+                    x: scaled.width().min(total.x),
+                    y: scaled.height().min(total.y)
+                };
+
+                let position = Vec2{x: (total.x - size.x) / 2, y: (total.y - size.y) / 2};
+
+                let mut image = ImageBuffer::from_pixel(total.x, total.y, background);
+                imageops::replace(&mut image, &scaled, position.x as i64, position.y as i64);
+
+                (image, Some((position, size)))
+            }
+        }
+    }
+
+    // the tiles each cell got in the previous frame, in the same order collage returns them
+    pub fn set_previous(&mut self, indices: &[usize])
+    {
+        let previous = self.positions_iter().zip(indices.iter().copied()).collect();
+
+        self.previous = previous;
     }
 
-    #[allow(dead_code)]
-    fn best_fit_index<'a>(
+    pub fn collage(
         &self,
-        images: impl Iterator<Item=&'a LabImage>,
-        position: Vec2
-    ) -> usize
+        imager: &Imager,
+        libraries: &Libraries
+    ) -> Result<Collage, imager::Error>
     {
-        Self::best_fit_index_assoc(
-            &self.image,
-            images,
-            position,
-            Vec2{x: self.pixel_size, y: self.pixel_size}
-        )
+        let images = imager.images();
+
+        let Libraries(libraries) = libraries;
+        let placements = self.matched(libraries);
+
+        let manifest = self.manifest.then(|| self.manifest_of(&placements, libraries, imager));
+
+        let rendered = self.render_size.is_some().then(||
+        {
+            self.render_tiles(&placements, |index| imager.tile_source(index))
+        }).transpose()?;
+
+        let collage = self.construct_from_placements(
+            &placements,
+            &images,
+            libraries,
+            rendered.as_ref(),
+            None
+        );
+
+        Ok(Collage{
+            image: self.finished(collage),
+            indices: placements.iter().map(|placement| placement.index).collect(),
+            manifest
+        })
     }
 
-    #[allow(dead_code)]
-    fn best_fit_index_assoc<'a>(
-        image: &LabImage,
-        images: impl Iterator<Item=&'a LabImage>,
-        position: Vec2,
-        size: Vec2
-    ) -> usize
+    // only matches without drawing anything, for when the collage is too big to draw at once
+    pub fn describe(&self, imager: &Imager, libraries: &Libraries) -> Manifest
     {
-        let subimage = image.subimage_pixels(position, size);
+        let Libraries(libraries) = libraries;
 
-        Self::best_fit_index_associated(subimage.iter().copied(), images)
+        self.manifest_of(&self.matched(libraries), libraries, imager)
     }
 
-    fn best_fit_index_associated<'a, I>(
-        subimage: I,
-        images: impl Iterator<Item=&'a LabImage>
-    ) -> usize
-    where
-        I: Iterator<Item=Lab> + Clone
+    // the tiles at every cell size, they only depend on the imager and the config
+    // so every frame of an animation can share them
+    pub fn libraries(&self, imager: &Imager) -> Libraries
     {
-        struct BestFit
+        let images = imager.images();
+        let lab_images = imager.lab_images();
+
+        // pixels outside the mask are zeroed on both sides so they add no error
+        // and every bound still holds
+        let lab_images = match self.mask.as_ref()
         {
-            index: usize,
-            error: f32
-        }
+            Some(mask) =>
+            {
+                Arc::new(lab_images.iter().map(|image|
+                {
+                    let values = image.pixels().zip(mask).flat_map(|(pixel, inside)|
+                    {
+                        let pixel = if *inside { pixel } else { Lab{l: 0.0, a: 0.0, b: 0.0} };
 
-        let images = images.enumerate();
+                        [pixel.l, pixel.a, pixel.b]
+                    }).collect();
 
-        let mut best_fit = BestFit{
-            index: 0,
-            error: f32::INFINITY
+                    LabImage::from_lab_values(image.width(), image.height(), values, self.space)
+                        .expect("mask must be the same size as the images")
+                }).collect())
+            },
+            None => lab_images
         };
 
-        images.for_each(|(index, image)|
+        let libraries = self.level_sizes().into_iter().enumerate().map(|(level, size)|
         {
-            let error = Self::pixels_error_early_exit(
-                subimage.clone(),
-                image.pixels(),
-                best_fit.error
-            );
+            let rgb = (level != 0).then(||
+            {
+                self.pool.map(images.iter(), |pair|
+                {
+                    imageops::resize(&pair.image, size.x, size.y, FilterType::Triangle)
+                })
+            });
 
-            if let Some(error) = error
+            let lab_images = match rgb.as_ref()
             {
-                if error < best_fit.error
+                Some(rgb) => Arc::new(self.pool.map(rgb.iter(), |image|
                 {
-                    best_fit = BestFit{index, error};
-                }
+                    LabImage::from_rgb(image.clone(), self.space)
+                })),
+                None => lab_images.clone()
+            };
+
+            self.library(&images, lab_images, rgb)
+        }).collect::<Vec<_>>();
+
+        Libraries(libraries)
+    }
+
+    // where each of the library tiles goes
+    fn matched(&self, libraries: &[Library]) -> Vec<Placement>
+    {
+        match self.quadtree
+        {
+            Some(quadtree) => self.quadtree_placements(libraries, quadtree),
+            None =>
+            {
+                let output_size = self.output_tile_size();
+
+                let indices = self.best_indices(&libraries[0]);
+
+                let cells = self.cells_iter().zip(self.positions_iter());
+                cells.zip(indices).map(|((cell, position), index)|
+                {
+                    Placement{
+                        cell,
+                        position,
+                        size: self.tile_size,
+                        output: self.with_margin(self.grid.position(cell, self.pitch())),
+                        output_size,
+                        level: 0,
+                        index
+                    }
+                }).collect()
             }
-        });
+        }
+    }
+
+    // draws the cells of a manifest again with every tile loaded from its files, the
+    // positions get worked out again if relayout is set and are taken as they are otherwise
+    pub fn render_manifest(
+        &self,
+        manifest: &Manifest,
+        relayout: bool
+    ) -> Result<Collage, imager::Error>
+    {
+        let (placements, sources) = self.manifest_placements(manifest, relayout);
+
+        let rendered = self.render_tiles(&placements, |index| sources[index].clone())?;
+
+        let matched = self.correction.is_some().then(||
+        {
+            self.matched_tiles(&placements, |index| sources[index].clone())
+        }).transpose()?;
 
-        best_fit.index
+        // every tile is already rendered so theres no library to take them from
+        let collage = self.construct_from_placements(
+            &placements,
+            &Vec::new(),
+            &[],
+            Some(&rendered),
+            matched.as_ref()
+        );
+
+        Ok(Collage{
+            image: self.finished(collage),
+            indices: placements.iter().map(|placement| placement.index).collect(),
+            manifest: Some(self.manifest_with(Self::placed_cells(manifest, &placements)))
+        })
     }
 
-    fn pixels_error_early_exit<'a, A, B>(a: A, b: B, min_bound: f32) -> Option<f32>
-    where
-        A: Iterator<Item=Lab>,
-        B: Iterator<Item=Lab>
+    // the manifest with its cells moved to where render_manifest would draw them
+    pub fn relaid_manifest(&self, manifest: &Manifest, relayout: bool) -> Manifest
     {
-        let error = a.zip(b).map(|(a, b)|
+        let (placements, _) = self.manifest_placements(manifest, relayout);
+
+        self.manifest_with(Self::placed_cells(manifest, &placements))
+    }
+
+    // hand edited cells can point anywhere, so every cell has to be inside the target and
+    // (unless they get laid out again) inside the collage before any of them get drawn
+    pub fn check_manifest(&self, manifest: &Manifest, relayout: bool) -> Result<(), String>
+    {
+        let fits = |position: u32, length: u32, total: u32|
         {
-            if SQRT_DISTANCE
+            length > 0 && position.checked_add(length).is_some_and(|end| end <= total)
+        };
+
+        let output_size = self.output_size();
+        let tile_size = self.output_tile_size();
+
+        // the masks only line up with cells of the usual size
+        let masked = self.grid.mask(tile_size).is_some();
+
+        manifest.cells.iter().enumerate().try_for_each(|(index, cell)|
+        {
+            let in_target = fits(cell.target_x, cell.target_width, self.image.width())
+                && fits(cell.target_y, cell.target_height, self.image.height());
+
+            if !in_target
             {
-                a.distance(b).sqrt()
-            } else
+                return Err(format!("cell {index} is outside of the target"));
+            }
+
+            if relayout
             {
-                a.distance(b)
+                return Ok(());
             }
-        }).try_fold(0.0, |mut acc, distance|
-        {
-            acc += distance;
 
-            if acc >= min_bound
+            let size = Vec2{x: cell.width.max(1), y: cell.height.max(1)};
+
+            if !fits(cell.x, size.x, output_size.x) || !fits(cell.y, size.y, output_size.y)
             {
-                ControlFlow::Break(())
-            } else
+                return Err(format!(
+                    "cell {index} is outside of the {}x{} collage",
+                    output_size.x,
+                    output_size.y
+                ));
+            }
+
+            if masked && size != tile_size
             {
-                ControlFlow::Continue(acc)
+                return Err(format!(
+                    "cell {index} has to be {}x{} to fit the grid",
+                    tile_size.x,
+                    tile_size.y
+                ));
             }
-        });
 
-        match error
+            Ok(())
+        })
+    }
+
+    // the placements of the cells in a manifest and the files their indices point to
+    fn manifest_placements(
+        &self,
+        manifest: &Manifest,
+        relayout: bool
+    ) -> (Vec<Placement>, Vec<TileSource>)
+    {
+        let mut sources: Vec<TileSource> = Vec::new();
+        let mut indices: HashMap<TileSource, usize> = HashMap::new();
+
+        let placements = manifest.cells.iter().map(|cell|
         {
-            ControlFlow::Continue(x) => Some(x),
-            ControlFlow::Break(_) => None
+            let index = *indices.entry(cell.tile_source()).or_insert_with_key(|source|
+            {
+                sources.push(source.clone());
+
+                sources.len() - 1
+            });
+
+            let cell_position = Vec2{x: cell.column, y: cell.row};
+
+            let position = Vec2{x: cell.target_x, y: cell.target_y};
+            let size = Vec2{x: cell.target_width, y: cell.target_height};
+
+            let (output, output_size) = if !relayout
+            {
+                (Vec2{x: cell.x, y: cell.y}, Vec2{x: cell.width.max(1), y: cell.height.max(1)})
+            } else if cell.level == 0
+            {
+                let output = self.with_margin(self.grid.position(cell_position, self.pitch()));
+
+                (output, self.output_tile_size())
+            } else
+            {
+                self.quadtree_output(position, size)
+            };
+
+            Placement{
+                cell: cell_position,
+                position,
+                size,
+                output,
+                output_size,
+                level: cell.level,
+                index
+            }
+        }).collect::<Vec<_>>();
+
+        (placements, sources)
+    }
+
+    fn placed_cells(manifest: &Manifest, placements: &[Placement]) -> Vec<ManifestCell>
+    {
+        manifest.cells.iter().zip(placements).map(|(cell, placement)|
+        {
+            ManifestCell{
+                x: placement.output.x,
+                y: placement.output.y,
+                width: placement.output_size.x,
+                height: placement.output_size.y,
+                ..cell.clone()
+            }
+        }).collect()
+    }
+
+    // blends the overlay on top and drops the alpha if nothing can be transparent
+    fn finished(&self, mut collage: RgbaImage) -> DynamicImage
+    {
+        if let Some((overlay, target)) = self.overlay.as_ref()
+        {
+            let offset = self.with_margin(Vec2{x: 0, y: 0});
+
+            overlay.apply(&mut collage, target, offset, self.space);
+        }
+
+        if self.grout.color.is_some()
+        {
+            DynamicImage::ImageRgb8(collage.convert())
+        } else
+        {
+            DynamicImage::ImageRgba8(collage)
+        }
+    }
+
+    // amount of columns and rows the grid ended up with
+    pub fn cells(&self) -> (u32, u32)
+    {
+        (self.width, self.height)
+    }
+
+    fn library(
+        &self,
+        images: &ImagesContainer,
+        lab_images: Arc<LabImagesContainer>,
+        rgb: Option<Vec<RgbImage>>
+    ) -> Library
+    {
+        let index = match self.search
+        {
+            Search::Indexed{..} => Some(TileIndex::new(lab_images.iter())),
+            Search::Linear => None
+        };
+
+        Library{
+            images: lab_images,
+            rgb,
+            index,
+            sources: images.iter().map(|pair| pair.source).collect(),
+            sources_amount: images.iter().map(|pair| pair.source + 1).max().unwrap_or(0)
+        }
+    }
+
+    // size of the output tiles at the full cell size
+    fn output_tile_size(&self) -> Vec2
+    {
+        self.render_size.unwrap_or(self.tile_size)
+    }
+
+    // output tile size with the gap after it
+    fn pitch(&self) -> Vec2
+    {
+        let size = self.output_tile_size();
+
+        Vec2{x: size.x + self.grout.gap, y: size.y + self.grout.gap}
+    }
+
+    fn with_margin(&self, position: Vec2) -> Vec2
+    {
+        Vec2{x: position.x + self.grout.margin, y: position.y + self.grout.margin}
+    }
+
+    // cell size at every quadtree level, halving while it still splits evenly
+    fn level_sizes(&self) -> Vec<Vec2>
+    {
+        let mut sizes = vec![self.tile_size];
+
+        if let Some(quadtree) = self.quadtree
+        {
+            let mut size = self.tile_size;
+            while size.x.is_multiple_of(2) && size.y.is_multiple_of(2)
+                && size.x.min(size.y) / 2 >= quadtree.min_size.max(1)
+            {
+                size = Vec2{x: size.x / 2, y: size.y / 2};
+
+                sizes.push(size);
+            }
+        }
+
+        sizes
+    }
+
+    fn quadtree_placements(&self, libraries: &[Library], quadtree: Quadtree) -> Vec<Placement>
+    {
+        let roots = self.pool.map(self.cells_iter().zip(self.positions_iter()), |(cell, position)|
+        {
+            let mut placements = Vec::new();
+
+            self.subdivide(libraries, quadtree, cell, position, 0, &mut placements);
+
+            placements
+        });
+
+        roots.into_iter().flatten().collect()
+    }
+
+    fn subdivide(
+        &self,
+        libraries: &[Library],
+        quadtree: Quadtree,
+        cell: Vec2,
+        position: Vec2,
+        level: usize,
+        placements: &mut Vec<Placement>
+    )
+    {
+        let size = Vec2{x: self.tile_size.x >> level, y: self.tile_size.y >> level};
+
+        let fit = self.best_fits(&libraries[level], position, size, 1, |_| true).first().copied()
+            .unwrap_or(Fit{index: 0, error: 0.0});
+
+        let error = Self::mean_error(fit.error, (size.x * size.y) as usize);
+
+        if error > quadtree.threshold && (level + 1) < libraries.len()
+        {
+            let half = Vec2{x: size.x / 2, y: size.y / 2};
+
+            [(0, 0), (half.x, 0), (0, half.y), (half.x, half.y)].into_iter().for_each(|(x, y)|
+            {
+                let position = Vec2{x: position.x + x, y: position.y + y};
+
+                self.subdivide(libraries, quadtree, cell, position, level + 1, placements);
+            });
+
+            return;
+        }
+
+        let (output, output_size) = self.quadtree_output(position, size);
+
+        placements.push(Placement{
+            cell,
+            position,
+            size,
+            output,
+            output_size,
+            level,
+            index: fit.index
+        });
+    }
+
+    // where a quadtree leaf at position in the target goes in the output and how big it is
+    fn quadtree_output(&self, position: Vec2, size: Vec2) -> (Vec2, Vec2)
+    {
+        // both edges get scaled so neighbouring tiles never leave gaps, smaller
+        // tiles split the spacing too so theyre all the same distance apart
+        let pitch = self.pitch();
+        let scale = |value: u32, output: u32, tile: u32|
+        {
+            (value as u64 * output as u64 / tile as u64) as u32
+        };
+
+        let scale = |value: Vec2|
+        {
+            Vec2{
+                x: scale(value.x, pitch.x, self.tile_size.x),
+                y: scale(value.y, pitch.y, self.tile_size.y)
+            }
+        };
+
+        let output = scale(position);
+        let end = scale(Vec2{x: position.x + size.x, y: position.y + size.y});
+
+        let gap = self.grout.gap;
+
+        let output_size = Vec2{
+            x: (end.x - output.x).saturating_sub(gap).max(1),
+            y: (end.y - output.y).saturating_sub(gap).max(1)
+        };
+
+        (self.with_margin(output), output_size)
+    }
+
+    // loads every used tile again from its file at the size its drawn at
+    fn render_tiles(
+        &self,
+        placements: &[Placement],
+        source: impl Fn(usize) -> TileSource + Sync
+    ) -> Result<HashMap<(usize, Vec2), RgbImage>, imager::Error>
+    {
+        let mut unique = placements.iter().map(|placement|
+        {
+            (placement.index, placement.output_size)
+        }).collect::<Vec<_>>();
+
+        unique.sort_unstable();
+        unique.dedup();
+
+        let tiles = self.pool.map(unique.iter(), |(index, size)|
+        {
+            source(*index).load(*size)
+        });
+
+        unique.into_iter().zip(tiles).map(|(key, tile)|
+        {
+            tile.map(|tile| (key, tile))
+        }).collect()
+    }
+
+    // the tiles the way the libraries had them when matching, for the color correction
+    fn matched_tiles(
+        &self,
+        placements: &[Placement],
+        source: impl Fn(usize) -> TileSource + Sync
+    ) -> Result<HashMap<(usize, Vec2), LabImage>, imager::Error>
+    {
+        let mut unique = placements.iter().map(|placement|
+        {
+            (placement.index, placement.size)
+        }).collect::<Vec<_>>();
+
+        unique.sort_unstable();
+        unique.dedup();
+
+        let tiles = self.pool.map(unique.iter(), |(index, size)|
+        {
+            source(*index).load(self.tile_size).map(|tile|
+            {
+                // smaller quadtree levels get shrunk from the full size tiles
+                let tile = if *size != self.tile_size
+                {
+                    imageops::resize(&tile, size.x, size.y, FilterType::Triangle)
+                } else
+                {
+                    tile
+                };
+
+                LabImage::from_rgb(tile, self.space)
+            })
+        });
+
+        unique.into_iter().zip(tiles).map(|(key, tile)|
+        {
+            tile.map(|tile| (key, tile))
+        }).collect()
+    }
+
+    // every cell that covers any of the target, cells only covering padding are skipped
+    fn cells_iter(&self) -> impl Iterator<Item=Vec2> + '_
+    {
+        (0..self.height).flat_map(move |y|
+        {
+            (0..self.width).map(move |x| Vec2{x, y})
+        }).filter(|cell|
+        {
+            self.content.map(|(position, size)|
+            {
+                let cell_position = self.grid.position(*cell, self.tile_size);
+
+                let overlaps = |start: u32, length: u32, cell_start: u32, cell_length: u32|
+                {
+                    cell_start < start + length && start < cell_start + cell_length
+                };
+
+                overlaps(position.x, size.x, cell_position.x, self.tile_size.x)
+                    && overlaps(position.y, size.y, cell_position.y, self.tile_size.y)
+            }).unwrap_or(true)
+        })
+    }
+
+    fn positions_iter(&self) -> impl Iterator<Item=Vec2> + '_
+    {
+        self.cells_iter().map(|cell| self.grid.position(cell, self.tile_size))
+    }
+
+    // the target pixels of the cell at position, zeroed outside the mask
+    fn cell_pixels(&self, position: Vec2, size: Vec2) -> Vec<Lab>
+    {
+        let mut pixels = self.image.subimage_pixels(position, size);
+
+        if let Some(mask) = self.mask.as_ref()
+        {
+            pixels.iter_mut().zip(mask).filter(|(_, inside)| !**inside).for_each(|(pixel, _)|
+            {
+                *pixel = Lab{l: 0.0, a: 0.0, b: 0.0};
+            });
+        }
+
+        pixels
+    }
+
+    fn inside_mask<'a>(
+        &'a self,
+        pixels: impl Iterator<Item=Lab> + Clone + 'a
+    ) -> impl Iterator<Item=Lab> + Clone + 'a
+    {
+        pixels.enumerate().filter(|(index, _)|
+        {
+            self.mask.as_ref().map(|mask| mask[*index]).unwrap_or(true)
+        }).map(|(_, pixel)| pixel)
+    }
+
+    fn manifest_of(
+        &self,
+        placements: &[Placement],
+        libraries: &[Library],
+        imager: &Imager
+    ) -> Manifest
+    {
+        let cells = self.pool.map(placements.iter(), |placement|
+        {
+            let Placement{cell, position, size, output, output_size, level, index} = *placement;
+
+            let source = imager.tile_source(index);
+
+            let target = self.cell_pixels(position, size);
+            let error = Self::pixels_error_early_exit(
+                target.into_iter(),
+                libraries[level].images[index].pixels(),
+                self.metric,
+                f32::INFINITY
+            ).unwrap_or(f32::INFINITY);
+
+            ManifestCell{
+                column: cell.x,
+                row: cell.y,
+                level,
+                target_x: position.x,
+                target_y: position.y,
+                target_width: size.x,
+                target_height: size.y,
+                x: output.x,
+                y: output.y,
+                width: output_size.x,
+                height: output_size.y,
+                source: source.path,
+                transform: source.transform,
+                overlays: source.overlays.into_iter().map(|(path, transform)|
+                {
+                    ManifestLayer{path, transform}
+                }).collect(),
+                error: Self::mean_error(error, self.inside_pixels(size))
+            }
+        });
+
+        self.manifest_with(cells)
+    }
+
+    // the manifest of this collage with these cells in it
+    fn manifest_with(&self, cells: Vec<ManifestCell>) -> Manifest
+    {
+        let render_size = self.output_tile_size();
+        let size = self.output_size();
+
+        Manifest{
+            target: self.target_path.clone(),
+            grid: self.grid,
+            columns: self.width,
+            rows: self.height,
+            fit: self.fit,
+            background: colors::format_rgb(self.background),
+            tile_width: self.tile_size.x,
+            tile_height: self.tile_size.y,
+            render_width: render_size.x,
+            render_height: render_size.y,
+            width: size.x,
+            height: size.y,
+            gap: self.grout.gap,
+            margin: self.grout.margin,
+            corner_radius: self.grout.radius,
+            gap_color: self.grout.color.map(colors::format_rgb),
+            space: self.space,
+            correction: self.correction,
+            overlay: self.overlay.as_ref().map(|(overlay, _)| *overlay),
+            cells
+        }
+    }
+
+    // size of the whole output image with the grout around it
+    fn output_size(&self) -> Vec2
+    {
+        let size = self.grid.size(self.width, self.height, self.pitch());
+
+        let (gap, margin) = (self.grout.gap, self.grout.margin);
+        let spaced = |length: u32| length.saturating_sub(gap).max(1) + margin * 2;
+
+        Vec2{x: spaced(size.x), y: spaced(size.y)}
+    }
+
+    // how many pixels of a cell this size get matched
+    fn inside_pixels(&self, size: Vec2) -> usize
+    {
+        match self.mask.as_ref()
+        {
+            Some(mask) if size == self.tile_size => mask.iter().filter(|inside| **inside).count(),
+            _ => (size.x * size.y) as usize
+        }
+    }
+
+    // average distance per pixel from the total error of pixels many pixels
+    fn mean_error(error: f32, pixels: usize) -> f32
+    {
+        let error = error / pixels.max(1) as f32;
+
+        if SQRT_DISTANCE
+        {
+            error
+        } else
+        {
+            error.sqrt()
+        }
+    }
+
+    fn construct_from_placements(
+        &self,
+        placements: &[Placement],
+        images: &ImagesContainer,
+        libraries: &[Library],
+        rendered: Option<&HashMap<(usize, Vec2), RgbImage>>,
+        matched: Option<&HashMap<(usize, Vec2), LabImage>>
+    ) -> RgbaImage
+    {
+        let tile_size = self.output_tile_size();
+
+        let mut image =
+        {
+            let pixel = self.grout.color.map(|color| color.to_rgba())
+                .unwrap_or(Rgba([0, 0, 0, 0]));
+
+            let size = self.output_size();
+
+            ImageBuffer::from_pixel(size.x, size.y, pixel)
+        };
+
+        // only the hex grids have masks and their tiles are all the same size
+        let mask = self.grid.mask(tile_size);
+
+        placements.iter().for_each(|placement|
+        {
+            let Placement{position, size, output, output_size, level, index, ..} = *placement;
+
+            let tile = match rendered
+            {
+                Some(rendered) => &rendered[&(index, output_size)],
+                None => libraries[level].rgb.as_ref().map(|rgb| &rgb[index])
+                    .unwrap_or(&images[index].image)
+            };
+
+            // smaller tiles shrink a bit more than their library images because of the gaps
+            let resized = (tile.dimensions() != (output_size.x, output_size.y)).then(||
+            {
+                imageops::resize(tile, output_size.x, output_size.y, FilterType::CatmullRom)
+            });
+
+            let tile = resized.as_ref().unwrap_or(tile);
+
+            let corrected = self.correction.map(|correction|
+            {
+                let matched = match matched
+                {
+                    Some(matched) => &matched[&(index, size)],
+                    None => &libraries[level].images[index]
+                };
+
+                self.corrected_tile(&position, size, matched, tile, correction)
+            });
+
+            let tile = corrected.as_ref().unwrap_or(tile);
+
+            let mut copy_pixel = |x, y|
+            {
+                if let Some(mask) = mask.as_ref()
+                {
+                    if !mask[(y * output_size.x + x) as usize]
+                    {
+                        return;
+                    }
+                }
+
+                if !self.grout.covers(x, y, output_size)
+                {
+                    return;
+                }
+
+                let pixel = tile.get_pixel(x, y);
+
+                image.put_pixel(output.x + x, output.y + y, pixel.to_rgba());
+            };
+
+            for y in 0..output_size.y
+            {
+                for x in 0..output_size.x
+                {
+                    copy_pixel(x, y);
+                }
+            }
+        });
+
+        image
+    }
+
+    // shifts the tiles mean (and optionally the spread) towards the target cell in lab,
+    // the statistics come from the matched tile which might be smaller than the output one
+    fn corrected_tile(
+        &self,
+        position: &Vec2,
+        size: Vec2,
+        matched: &LabImage,
+        tile: &RgbImage,
+        correction: ColorCorrection
+    ) -> RgbImage
+    {
+        let target = self.image.subimage_pixels(*position, size);
+
+        let target = self.inside_mask(target.iter().copied());
+        let (target_mean, target_deviation) = Self::lab_statistics(target);
+        let (tile_mean, tile_deviation) = Self::lab_statistics(self.inside_mask(matched.pixels()));
+
+        let tile = LabImage::from_rgb(tile.clone(), self.space);
+
+        let scale = |target: f32, tile: f32|
+        {
+            // flat tiles cant be stretched into anything meaningful
+            if correction.match_deviation && tile > 0.001
+            {
+                target / tile
+            } else
+            {
+                1.0
+            }
+        };
+
+        let scale_l = scale(target_deviation.l, tile_deviation.l);
+        let scale_a = scale(target_deviation.a, tile_deviation.a);
+        let scale_b = scale(target_deviation.b, tile_deviation.b);
+
+        let strength = correction.strength;
+        let pixels = tile.pixels().map(|pixel|
+        {
+            let corrected = Lab{
+                l: target_mean.l + (pixel.l - tile_mean.l) * scale_l,
+                a: target_mean.a + (pixel.a - tile_mean.a) * scale_a,
+                b: target_mean.b + (pixel.b - tile_mean.b) * scale_b
+            };
+
+            let lab = Lab{
+                l: pixel.l + (corrected.l - pixel.l) * strength,
+                a: pixel.a + (corrected.a - pixel.a) * strength,
+                b: pixel.b + (corrected.b - pixel.b) * strength
+            };
+
+            self.space.to_rgb(lab).map(|value| value.clamp(0.0, 1.0))
+        });
+
+        let mut output = Rgb32FImage::new(tile.width(), tile.height());
+        output.pixels_mut().zip(pixels).for_each(|(pixel, corrected)| *pixel = corrected);
+
+        output.convert()
+    }
+
+    // mean and standard deviation of each channel
+    fn lab_statistics(pixels: impl Iterator<Item=Lab> + Clone) -> (Lab, Lab)
+    {
+        let amount = pixels.clone().count().max(1) as f32;
+
+        let sum = pixels.clone().fold(Lab{l: 0.0, a: 0.0, b: 0.0}, |acc, pixel|
+        {
+            Lab{l: acc.l + pixel.l, a: acc.a + pixel.a, b: acc.b + pixel.b}
+        });
+
+        let mean = Lab{l: sum.l / amount, a: sum.a / amount, b: sum.b / amount};
+
+        let variance_sum = pixels.fold(Lab{l: 0.0, a: 0.0, b: 0.0}, |acc, pixel|
+        {
+            Lab{
+                l: acc.l + (pixel.l - mean.l).powi(2),
+                a: acc.a + (pixel.a - mean.a).powi(2),
+                b: acc.b + (pixel.b - mean.b).powi(2)
+            }
+        });
+
+        let deviation = Lab{
+            l: (variance_sum.l / amount).sqrt(),
+            a: (variance_sum.a / amount).sqrt(),
+            b: (variance_sum.b / amount).sqrt()
+        };
+
+        (mean, deviation)
+    }
+
+    // the tile this cell had in the previous frame and the penalty for picking any other one
+    fn switch_penalty(&self, position: Vec2) -> Option<(usize, f32)>
+    {
+        let cost = self.switch_cost?;
+        let previous = *self.previous.get(&position)?;
+
+        Some((previous, cost * self.inside_pixels(self.tile_size) as f32))
+    }
+
+    fn best_indices(&self, library: &Library) -> Vec<usize>
+    {
+        match self.assignment
+        {
+            Assignment::Optimal => return self.optimal_indices(library),
+            Assignment::Approximate => return self.approximate_indices(library),
+            Assignment::Greedy => ()
+        }
+
+        if let Some(strength) = self.diffusion
+        {
+            return self.diffused_indices(library, strength);
+        }
+
+        if let Some(limits) = self.reuse
+        {
+            return self.limited_indices(library, limits);
+        }
+
+        self.pool.map(self.positions_iter(), |position|
+        {
+            let fits = self.best_fits(library, position, self.tile_size, 1, |_| true);
+
+            fits.first().map(|fit| fit.index).unwrap_or(0)
+        })
+    }
+
+    // goes cell by cell, every cell picks the best tile thats still allowed
+    fn limited_indices(&self, library: &Library, limits: ReuseLimits) -> Vec<usize>
+    {
+        let positions = self.positions_iter().collect::<Vec<_>>();
+
+        // the best few are usually enough and can be found in parallel
+        let shortlists = self.pool.map(positions.iter().copied(), |position|
+        {
+            self.best_fits(library, position, self.tile_size, REUSE_SHORTLIST, |_| true)
+        });
+
+        let mut usage = ReuseTracker::new(limits);
+
+        let cells = self.cells_iter().zip(positions).zip(shortlists);
+        cells.map(|((cell, position), shortlist)|
+        {
+            let allowed = |index: usize| usage.allowed(library.sources[index], cell);
+
+            // if nothing is allowed anymore just use the best one anyway
+            let fit = shortlist.iter().find(|fit| allowed(fit.index)).copied()
+                .or_else(||
+                {
+                    self.best_fits(library, position, self.tile_size, 1, allowed).first().copied()
+                })
+                .or_else(|| shortlist.first().copied());
+
+            let index = fit.map(|fit| fit.index).unwrap_or(0);
+
+            usage.add(library.sources[index], cell);
+
+            index
+        }).collect()
+    }
+
+    // cells get matched in serpentine order and push whatever their tile got wrong
+    // onto the neighbours that arent matched yet (floyd steinberg)
+    fn diffused_indices(&self, library: &Library, strength: f32) -> Vec<usize>
+    {
+        let (width, height) = (self.width as usize, self.height as usize);
+
+        let cells = self.cells_iter().collect::<Vec<_>>();
+        let present = cells.iter().copied().collect::<HashSet<_>>();
+
+        let mut errors = vec![Lab{l: 0.0, a: 0.0, b: 0.0}; width * height];
+        let mut chosen: HashMap<Vec2, usize> = HashMap::new();
+
+        let mut usage = self.reuse.map(ReuseTracker::new);
+
+        let serpentine = (0..height).flat_map(|y|
+        {
+            let reversed = y % 2 == 1;
+
+            (0..width).map(move |x| (if reversed { width - 1 - x } else { x }, y, reversed))
+        });
+
+        for (x, y, reversed) in serpentine
+        {
+            let cell = Vec2{x: x as u32, y: y as u32};
+
+            // cells only covering padding
+            if !present.contains(&cell)
+            {
+                continue;
+            }
+
+            let error = errors[y * width + x];
+
+            let position = self.grid.position(cell, self.tile_size);
+
+            let mut target = self.cell_pixels(position, self.tile_size);
+            target.iter_mut().enumerate().filter(|(index, _)|
+            {
+                self.mask.as_ref().map(|mask| mask[*index]).unwrap_or(true)
+            }).for_each(|(_, pixel)|
+            {
+                *pixel = Lab{l: pixel.l + error.l, a: pixel.a + error.a, b: pixel.b + error.b};
+            });
+
+            let allowed = |index: usize|
+            {
+                usage.as_ref().map(|usage| usage.allowed(library.sources[index], cell))
+                    .unwrap_or(true)
+            };
+
+            // cells depend on the ones before them so only the search of one can be split up,
+            // every thread takes every nth image
+            let chunks = self.pool.jobs().min(library.images.len() / DIFFUSION_CHUNK).max(1);
+
+            let best = |allowed: &(dyn Fn(usize) -> bool + Sync)|
+            {
+                let switch = self.switch_penalty(position);
+                let kept = switch.map(|(index, _)| index);
+
+                let best_in = |chunk: usize|
+                {
+                    let allowed = |index: usize| index % chunks == chunk && allowed(index);
+
+                    self.best_fits_to(library, &target, self.tile_size, switch, 1, allowed)
+                        .first().copied()
+                };
+
+                if chunks == 1
+                {
+                    return best_in(0);
+                }
+
+                // ties go to whatever a single search would have found first
+                self.pool.map(0..chunks, best_in).into_iter().flatten().min_by(|a, b|
+                {
+                    a.error.total_cmp(&b.error)
+                        .then((Some(a.index) != kept).cmp(&(Some(b.index) != kept)))
+                        .then(a.index.cmp(&b.index))
+                })
+            };
+
+            // if nothing is allowed anymore just use the best one anyway
+            let fit = best(&allowed).or_else(|| best(&|_| true)).map(|fit| fit.index).unwrap_or(0);
+
+            if let Some(usage) = usage.as_mut()
+            {
+                usage.add(library.sources[fit], cell);
+            }
+
+            chosen.insert(cell, fit);
+
+            let tile = &library.images[fit];
+
+            let (target_mean, _) = Self::lab_statistics(self.inside_mask(target.iter().copied()));
+            let (tile_mean, _) = Self::lab_statistics(self.inside_mask(tile.pixels()));
+
+            let residual = Lab{
+                l: (target_mean.l - tile_mean.l) * strength,
+                a: (target_mean.a - tile_mean.a) * strength,
+                b: (target_mean.b - tile_mean.b) * strength
+            };
+
+            let forward = if reversed { -1 } else { 1 };
+            let neighbours = [
+                (forward, 0, 7.0),
+                (-forward, 1, 3.0),
+                (0, 1, 5.0),
+                (forward, 1, 1.0)
+            ];
+
+            neighbours.into_iter().for_each(|(dx, dy, weight)|
+            {
+                let x = x as isize + dx;
+                let y = y + dy;
+
+                if x < 0 || x as usize >= width || y >= height
+                {
+                    return;
+                }
+
+                let weight = weight / 16.0;
+                let error = &mut errors[y * width + x as usize];
+
+                let spread = |value: f32, residual: f32|
+                {
+                    (value + residual * weight).clamp(-DIFFUSION_LIMIT, DIFFUSION_LIMIT)
+                };
+
+                *error = Lab{
+                    l: spread(error.l, residual.l),
+                    a: spread(error.a, residual.a),
+                    b: spread(error.b, residual.b)
+                };
+            });
+        }
+
+        cells.iter().map(|cell| chosen[cell]).collect()
+    }
+
+    // how many times each source image can be used in the assignment modes, if
+    // there arent enough images for every cell it gets raised until there are
+    fn source_capacity(&self, library: &Library) -> usize
+    {
+        let cells = self.cells_iter().count();
+        let minimum = cells.div_ceil(library.sources_amount.max(1));
+
+        self.reuse.and_then(|limits| limits.max_uses).unwrap_or(1).min(cells).max(minimum)
+    }
+
+    fn optimal_indices(&self, library: &Library) -> Vec<usize>
+    {
+        if library.images.is_empty()
+        {
+            return vec![0; self.cells_iter().count()];
+        }
+
+        let columns = library.sources_amount;
+
+        // the best transformed copy of every source for each cell
+        let rows = self.pool.map(self.positions_iter(), |position|
+        {
+            let subimage = self.cell_pixels(position, self.tile_size);
+            let switch = self.switch_penalty(position);
+
+            let mut best = vec![Fit{index: 0, error: f32::INFINITY}; columns];
+
+            library.images.iter().enumerate().for_each(|(index, image)|
+            {
+                let best = &mut best[library.sources[index]];
+
+                let penalty = Self::penalty_for(switch, index);
+
+                let error = Self::pixels_error_early_exit(
+                    subimage.iter().copied(),
+                    image.pixels(),
+                    self.metric,
+                    best.error - penalty
+                );
+
+                if let Some(error) = error
+                {
+                    *best = Fit{index, error: error + penalty};
+                }
+            });
+
+            best
+        });
+
+        let costs = rows.iter().flat_map(|row|
+        {
+            // sources without any images (failed permutations and such)
+            row.iter().map(|fit| if fit.error.is_finite() { fit.error } else { f32::MAX })
+        }).collect::<Vec<_>>();
+
+        let capacity = self.source_capacity(library);
+
+        let assigned = assignment::optimal(&costs, rows.len(), columns, capacity);
+
+        rows.iter().zip(assigned).map(|(row, source)| row[source].index).collect()
+    }
+
+    fn approximate_indices(&self, library: &Library) -> Vec<usize>
+    {
+        let positions = self.positions_iter().collect::<Vec<_>>();
+
+        let shortlists = self.pool.map(positions.iter().copied(), |position|
+        {
+            self.best_fits(library, position, self.tile_size, APPROXIMATE_SHORTLIST, |_| true)
+        });
+
+        let capacity = self.source_capacity(library);
+
+        let mut edges = shortlists.iter().enumerate().flat_map(|(cell, shortlist)|
+        {
+            shortlist.iter().map(move |fit| (cell, *fit))
+        }).collect::<Vec<_>>();
+
+        edges.sort_by(|(_, a), (_, b)| a.error.total_cmp(&b.error));
+
+        let mut uses = vec![0; library.sources_amount];
+        let mut assigned: Vec<Option<usize>> = vec![None; positions.len()];
+
+        edges.into_iter().for_each(|(cell, fit)|
+        {
+            let source = library.sources[fit.index];
+
+            if assigned[cell].is_none() && uses[source] < capacity
+            {
+                assigned[cell] = Some(fit.index);
+                uses[source] += 1;
+            }
+        });
+
+        assigned.into_iter().zip(positions).zip(shortlists).map(|((index, position), shortlist)|
+        {
+            index.unwrap_or_else(||
+            {
+                let fit = self.best_fits(library, position, self.tile_size, 1, |index|
+                {
+                    uses[library.sources[index]] < capacity
+                }).first().or(shortlist.first()).map(|fit| fit.index).unwrap_or(0);
+
+                uses[library.sources[fit]] += 1;
+
+                fit
+            })
+        }).collect()
+    }
+
+    // the best amount of tiles that pass allowed, sorted by their error
+    fn best_fits(
+        &self,
+        library: &Library,
+        position: Vec2,
+        size: Vec2,
+        amount: usize,
+        allowed: impl Fn(usize) -> bool
+    ) -> Vec<Fit>
+    {
+        let subimage = self.cell_pixels(position, size);
+
+        let switch = self.switch_penalty(position);
+
+        self.best_fits_to(library, &subimage, size, switch, amount, allowed)
+    }
+
+    // same as best_fits but against any pixels instead of the targets
+    fn best_fits_to(
+        &self,
+        library: &Library,
+        subimage: &[Lab],
+        size: Vec2,
+        switch: Option<(usize, f32)>,
+        amount: usize,
+        allowed: impl Fn(usize) -> bool
+    ) -> Vec<Fit>
+    {
+        let metric = self.metric;
+
+        let pyramid = Pyramid::new(subimage.iter().copied(), size);
+
+        let images = &library.images;
+        let candidates: Box<dyn Iterator<Item=Candidate>> = match (self.search, &library.index)
+        {
+            (Search::Indexed{candidates}, Some(index)) =>
+            {
+                let query = index::descriptor(&pyramid);
+
+                // the descriptor distance only bounds the squared cie76 error
+                let bound_valid = metric == DistanceMetric::Cie76 && !SQRT_DISTANCE;
+
+                let nearest = index.nearest(query).map(move |(index, bound)|
+                {
+                    Candidate{
+                        index,
+                        image: &images[index],
+                        bound: if bound_valid { bound } else { 0.0 }
+                    }
+                });
+
+                if let Some(candidates) = candidates
+                {
+                    Box::new(nearest.take(candidates))
+                } else
+                {
+                    Box::new(nearest)
+                }
+            },
+            _ =>
+            {
+                Box::new(images.iter().enumerate().map(|(index, image)|
+                {
+                    Candidate{index, image, bound: 0.0}
+                }))
+            }
+        };
+
+        // the previous tile goes first so it can rule out everything thats not worth switching to
+        let kept = switch.map(|(index, _)| index);
+        let previous = kept.map(|index| Candidate{index, image: &images[index], bound: 0.0});
+
+        let candidates = previous.into_iter().chain(candidates.filter(move |candidate|
+        {
+            Some(candidate.index) != kept
+        }));
+
+        // the pyramid bounds only hold for cie76
+        let pyramid = (metric == DistanceMetric::Cie76).then_some(&pyramid);
+
+        Self::best_fits_associated(
+            subimage.iter().copied(),
+            pyramid,
+            candidates,
+            metric,
+            switch,
+            amount,
+            allowed
+        )
+    }
+
+    fn penalty_for(switch: Option<(usize, f32)>, index: usize) -> f32
+    {
+        match switch
+        {
+            Some((previous, penalty)) if previous != index => penalty,
+            _ => 0.0
+        }
+    }
+
+    fn best_fits_associated<'a, I>(
+        subimage: I,
+        pyramid: Option<&Pyramid>,
+        candidates: impl Iterator<Item=Candidate<'a>>,
+        metric: DistanceMetric,
+        switch: Option<(usize, f32)>,
+        amount: usize,
+        allowed: impl Fn(usize) -> bool
+    ) -> Vec<Fit>
+    where
+        I: Iterator<Item=Lab> + Clone
+    {
+        let mut best_fits: Vec<Fit> = Vec::with_capacity(amount + 1);
+
+        for Candidate{index, image, bound} in candidates
+        {
+            let penalty = Self::penalty_for(switch, index);
+
+            // anything worse than this cant make it into the best fits
+            let worst_error = if best_fits.len() < amount
+            {
+                f32::INFINITY
+            } else
+            {
+                best_fits.last().map(|fit| fit.error).unwrap_or(f32::INFINITY)
+            };
+
+            // candidates come sorted by their bound, nothing after this can be better
+            if bound + penalty >= worst_error
+            {
+                break;
+            }
+
+            if !allowed(index)
+            {
+                continue;
+            }
+
+            // rule out candidates at the low resolutions first, its way cheaper
+            let ruled_out = pyramid.map(|pyramid|
+            {
+                pyramid.levels().iter().zip(image.pyramid().levels()).any(|(level, other)|
+                {
+                    level.bound(other, !SQRT_DISTANCE) + penalty >= worst_error
+                })
+            }).unwrap_or(false);
+
+            if ruled_out
+            {
+                continue;
+            }
+
+            let error = Self::pixels_error_early_exit(
+                subimage.clone(),
+                image.pixels(),
+                metric,
+                worst_error - penalty
+            );
+
+            if let Some(error) = error.map(|error| error + penalty)
+            {
+                let position = best_fits.partition_point(|fit| fit.error <= error);
+
+                best_fits.insert(position, Fit{index, error});
+                best_fits.truncate(amount);
+            }
+        }
+
+        best_fits
+    }
+
+    fn pixels_error_early_exit<A, B>(
+        a: A,
+        b: B,
+        metric: DistanceMetric,
+        min_bound: f32
+    ) -> Option<f32>
+    where
+        A: Iterator<Item=Lab>,
+        B: Iterator<Item=Lab>
+    {
+        let error = a.zip(b).map(|(a, b)|
+        {
+            let distance = metric.distance(a, b);
+
+            if SQRT_DISTANCE
+            {
+                distance.sqrt()
+            } else
+            {
+                distance
+            }
+        }).try_fold(0.0, |mut acc, distance|
+        {
+            acc += distance;
+
+            if acc >= min_bound
+            {
+                ControlFlow::Break(())
+            } else
+            {
+                ControlFlow::Continue(acc)
+            }
+        });
+
+        match error
+        {
+            ControlFlow::Continue(x) => Some(x),
+            ControlFlow::Break(_) => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use std::{env, fs, process};
+
+    use crate::imager::Transform;
+
+
+    #[test]
+    fn reuse_limits()
+    {
+        let mut tracker = ReuseTracker::new(ReuseLimits{
+            max_uses: Some(2),
+            min_distance: Some(2.0)
+        });
+
+        let cell = |x, y| Vec2{x, y};
+
+        assert!(tracker.allowed(0, cell(0, 0)));
+        tracker.add(0, cell(0, 0));
+
+        assert!(!tracker.allowed(0, cell(1, 1)));
+        assert!(tracker.allowed(0, cell(2, 0)));
+        assert!(tracker.allowed(1, cell(1, 1)));
+
+        tracker.add(0, cell(5, 5));
+
+        assert!(!tracker.allowed(0, cell(10, 10)));
+    }
+
+    #[test]
+    fn rounded_corners()
+    {
+        let grout = |radius| Grout{gap: 0, margin: 0, radius, color: None};
+        let size = Vec2{x: 10, y: 6};
+
+        assert!(grout(0).covers(0, 0, size));
+
+        assert!(!grout(3).covers(0, 0, size));
+        assert!(grout(3).covers(1, 1, size));
+        assert!(grout(3).covers(5, 0, size));
+        assert!(!grout(3).covers(9, 5, size));
+
+        // the radius cant be bigger than half the tile
+        assert!(grout(100).covers(5, 3, size));
+        assert!(!grout(100).covers(9, 0, size));
+    }
+
+    #[test]
+    fn output_dimensions()
+    {
+        let (white, red, blue) = (Rgb([255, 255, 255]), Rgb([255, 0, 0]), Rgb([0, 0, 255]));
+        let background = Rgb([0, 255, 0]);
+
+        // 100 by 70 doesnt split evenly into 8 pixel tiles, the left and bottom edges are marked
+        let target = RgbImage::from_fn(100, 70, |x, y|
+        {
+            if y >= 60 { blue } else if x < 10 { red } else { white }
+        });
+
+        let tile = Vec2{x: 8, y: 8};
+        let image_size = Vec2{x: 100, y: 70};
+
+        let framed = |sizes, fit|
+        {
+            let (columns, rows) = Collager::grid_cells(Grid::Square, tile, image_size, sizes, fit);
+            let total = Grid::Square.size(columns, rows, tile);
+
+            let (image, content) = Collager::framed_target(target.clone(), total, fit, background);
+
+            // every row of the target ends up under a row of cells
+            assert_eq!(image.dimensions(), (columns * tile.x, rows * tile.y));
+
+            (columns, rows, image, content)
+        };
+
+        let aspect = |columns: u32, rows: u32| (rows * tile.y) as f32 / (columns * tile.x) as f32;
+
+        for sizes in [(Some(7), None, None), (None, Some(9), None), (None, None, Some(50))]
+        {
+            let (columns, rows, image, _) = framed(sizes, FitMode::Fit);
+
+            // rounded to the closest amount of rows, so at most half a tile off
+            assert!((aspect(columns, rows) - 0.7).abs() * (columns * tile.x) as f32 <= 4.0);
+
+            assert_eq!(*image.get_pixel(image.width() - 1, image.height() - 1), blue);
+            assert_eq!(*image.get_pixel(0, 0), red);
+        }
+
+        assert_eq!(framed((Some(7), None, None), FitMode::Fit).1, 5);
+        assert_eq!(framed((None, Some(9), None), FitMode::Fit).0, 13);
+
+        let (columns, rows, ..) = framed((None, None, Some(50)), FitMode::Fit);
+        assert!((columns * rows).abs_diff(50) <= 2);
+
+        // fit shrinks the grid to keep the aspect, the others take the size as it is
+        let (columns, rows, ..) = framed((Some(5), Some(5), None), FitMode::Fit);
+        assert_eq!((columns, rows), (5, 4));
+
+        let (columns, rows, image, content) = framed((Some(5), Some(5), None), FitMode::Fill);
+        assert_eq!((columns, rows, content), (5, 5, None));
+
+        // the wider target loses its sides but keeps the bottom
+        assert_eq!(*image.get_pixel(0, 0), white);
+        assert_eq!(*image.get_pixel(20, 39), blue);
+
+        let (columns, rows, image, content) = framed((Some(5), Some(5), None), FitMode::Pad);
+        assert_eq!((columns, rows), (5, 5));
+
+        let (position, size) = content.unwrap();
+        assert_eq!((position, size), (Vec2{x: 0, y: 6}, Vec2{x: 40, y: 28}));
+
+        // the target sits in the middle with background above and below
+        assert_eq!(*image.get_pixel(20, 0), background);
+        assert_eq!(*image.get_pixel(20, 39), background);
+        assert_eq!(*image.get_pixel(0, 10), red);
+        assert_eq!(*image.get_pixel(20, 6 + 27), blue);
+    }
+
+    #[test]
+    fn color_correction()
+    {
+        let lab = |l| Lab{l, a: 0.0, b: 0.0};
+
+        let (mean, deviation) = Collager::lab_statistics([lab(10.0), lab(30.0)].into_iter());
+
+        assert_eq!((mean.l, mean.a), (20.0, 0.0));
+        assert_eq!((deviation.l, deviation.b), (10.0, 0.0));
+
+        // dark grey tile on a lighter and more contrasty target
+        let halves = |left, right|
+        {
+            RgbImage::from_fn(4, 4, move |x, _| Rgb([if x < 2 { left } else { right }; 3]))
+        };
+
+        let target = halves(90, 210);
+        let tile = halves(40, 60);
+
+        let collager = Collager::new(target, Config{width: Some(1), ..config(Vec2{x: 4, y: 4})});
+
+        let matched = LabImage::from_rgb(tile.clone(), ColorSpace::Cielab);
+
+        let corrected = |strength, match_deviation|
+        {
+            let correction = ColorCorrection{strength, match_deviation};
+
+            let corrected = collager.corrected_tile(
+                &Vec2{x: 0, y: 0},
+                Vec2{x: 4, y: 4},
+                &matched,
+                &tile,
+                correction
+            );
+
+            let lightness = |x| LabImage::from_rgb(corrected.clone(), ColorSpace::Cielab)
+                .subimage_pixels(Vec2{x, y: 0}, Vec2{x: 1, y: 1})[0].l;
+
+            (lightness(0), lightness(3))
+        };
+
+        let original = corrected(0.0, false);
+        let shifted = corrected(1.0, false);
+        let stretched = corrected(1.0, true);
+
+        // the mean moves up but the spread stays the same without matching the deviation
+        assert!(shifted.0 > original.0 + 10.0);
+        assert!(((shifted.1 - shifted.0) - (original.1 - original.0)).abs() < 1.0);
+
+        // the spread grows to the targets with it
+        assert!(stretched.1 - stretched.0 > (shifted.1 - shifted.0) * 2.0);
+    }
+
+    #[test]
+    fn render_from_manifest()
+    {
+        let directory = env::temp_dir().join(format!("collager-render-test-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let (red, green, blue) = (Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255]));
+
+        let cells = [red, blue].into_iter().enumerate().map(|(index, color)|
+        {
+            let source = directory.join(format!("tile{index}.png"));
+            RgbImage::from_pixel(4, 4, color).save(&source).unwrap();
+
+            ManifestCell{
+                column: index as u32,
+                row: 0,
+                level: 0,
+                target_x: index as u32 * 4,
+                target_y: 0,
+                target_width: 4,
+                target_height: 4,
+                x: index as u32 * 8,
+                y: 0,
+                width: 6,
+                height: 6,
+                source,
+                transform: Transform::default(),
+                overlays: Vec::new(),
+                error: 0.0
+            }
+        }).collect::<Vec<_>>();
+
+        let target = RgbImage::from_fn(8, 4, |x, _| if x < 4 { green } else { blue });
+
+        let collager = |correction: Option<ColorCorrection>|
+        {
+            Collager::new(target.clone(), Config{
+                grout: Grout{gap: 2, margin: 0, radius: 0, color: Some(Rgb([255, 255, 255]))},
+                render_size: Some(Vec2{x: 6, y: 6}),
+                correction,
+                ..config(Vec2{x: 4, y: 4})
+            })
+        };
+
+        let render = |correction: Option<ColorCorrection>, relayout: bool|
+        {
+            let collager = collager(correction);
+            let manifest = collager.manifest_with(cells.clone());
+
+            assert_eq!(collager.check_manifest(&manifest, relayout), Ok(()));
+
+            collager.render_manifest(&manifest, relayout).unwrap().image.into_rgb8()
+        };
+
+        let image = render(None, false);
+
+        assert_eq!(image.dimensions(), (14, 6));
+        assert_eq!(*image.get_pixel(0, 0), red);
+        assert_eq!(*image.get_pixel(7, 3), Rgb([255, 255, 255]));
+        assert_eq!(*image.get_pixel(13, 5), blue);
+
+        assert_eq!(render(None, true), image);
+
+        // a full correction turns the red tile into the green the target has there
+        let corrected = render(Some(ColorCorrection{strength: 1.0, match_deviation: false}), false);
+
+        let Rgb([r, g, _]) = *corrected.get_pixel(2, 2);
+        assert!(g > 200 && r < 50);
+
+        // a cell pushed past the edge of the 14 pixel wide collage
+        let mut moved = cells.clone();
+        moved[1].x = 12;
+
+        let collager = collager(None);
+        let manifest = collager.manifest_with(moved);
+
+        assert!(collager.check_manifest(&manifest, false).is_err());
+        assert_eq!(collager.check_manifest(&manifest, true), Ok(()));
+
+        moved = cells.clone();
+        moved[0].target_y = 2;
+
+        let manifest = collager.manifest_with(moved);
+        assert!(collager.check_manifest(&manifest, true).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn quadtree_split()
+    {
+        let (black, white) = (Rgb([0, 0, 0]), Rgb([255, 255, 255]));
+
+        let (imager, directory) = imager("quadtree", &[black, white], Vec2{x: 8, y: 8});
+
+        // a flat black cell and one with black and white quarters
+        let target = RgbImage::from_fn(16, 8, |x, y|
+        {
+            if x >= 8 && ((x - 8) < 4) != (y < 4) { white } else { black }
+        });
+
+        let collager = Collager::new(target, Config{
+            quadtree: Some(Quadtree{min_size: 4, threshold: 5.0}),
+            ..config(Vec2{x: 8, y: 8})
+        });
+
+        assert_eq!(collager.level_sizes(), vec![Vec2{x: 8, y: 8}, Vec2{x: 4, y: 4}]);
+
+        let Libraries(libraries) = collager.libraries(&imager);
+        let placements = collager.matched(&libraries);
+
+        let leaves = placements.iter().map(|placement|
+        {
+            (placement.position.x, placement.position.y, placement.size.x, placement.level)
+        }).collect::<Vec<_>>();
+
+        assert_eq!(
+            leaves,
+            vec![(0, 0, 8, 0), (8, 0, 4, 1), (12, 0, 4, 1), (8, 4, 4, 1), (12, 4, 4, 1)]
+        );
+
+        // the quarters alternate between the two tiles
+        let picks = placements[1..].iter().map(|placement| placement.index).collect::<Vec<_>>();
+        assert_eq!(picks[0], picks[3]);
+        assert_eq!(picks[1], picks[2]);
+        assert_ne!(picks[0], picks[1]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn error_diffusion()
+    {
+        let (imager, directory) = imager(
+            "diffusion",
+            &[Rgb([60, 60, 60]), Rgb([200, 200, 200])],
+            Vec2{x: 4, y: 4}
+        );
+
+        // a dark gradient thats still closer to the dark tile everywhere
+        let target = RgbImage::from_fn(32, 4, |x, _| Rgb([100 + (x / 4) as u8 * 3; 3]));
+
+        let target_lightness = Collager::lab_statistics(
+            LabImage::from_rgb(target.clone(), ColorSpace::Cielab).pixels()
+        ).0.l;
+
+        let lightness = |diffusion|
+        {
+            let collager = Collager::new(target.clone(), Config{
+                width: Some(8),
+                diffusion,
+                ..config(Vec2{x: 4, y: 4})
+            });
+
+            let Libraries(libraries) = collager.libraries(&imager);
+            let picks = collager.matched(&libraries).iter().map(|placement|
+            {
+                Collager::lab_statistics(libraries[0].images[placement.index].pixels()).0.l
+            }).collect::<Vec<_>>();
+
+            let mean = picks.iter().sum::<f32>() / picks.len() as f32;
+
+            (picks, mean)
+        };
+
+        let (plain, plain_mean) = lightness(None);
+        let (diffused, diffused_mean) = lightness(Some(1.0));
+
+        assert!(plain.iter().all(|l| *l == plain[0]));
+        assert_ne!(plain, diffused);
+
+        // mixing in the light tile gets the average closer to the target
+        assert!((diffused_mean - target_lightness).abs() < (plain_mean - target_lightness).abs());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn switch_cost()
+    {
+        let (dark, light) = (Rgb([100, 100, 100]), Rgb([110, 110, 110]));
+
+        let (imager, directory) = imager("switch", &[dark, light], Vec2{x: 4, y: 4});
+
+        // the second frame is only a bit closer to the light tile
+        let second = Rgb([106, 106, 106]);
+
+        let lab = |color| LabImage::from_rgb(RgbImage::from_pixel(1, 1, color), ColorSpace::Cielab)
+            .pixels().next().unwrap();
+
+        let gain = lab(second).distance(lab(dark)) - lab(second).distance(lab(light));
+        assert!(gain > 0.0);
+
+        // lightness of the tile the only cell gets in the second frame
+        let picked = |switch_cost|
+        {
+            let frame = |color, previous: Option<&[usize]>|
+            {
+                let mut collager = Collager::new(RgbImage::from_pixel(4, 4, color), Config{
+                    width: Some(1),
+                    height: Some(1),
+                    switch_cost,
+                    ..config(Vec2{x: 4, y: 4})
+                });
+
+                if let Some(previous) = previous
+                {
+                    collager.set_previous(previous);
+                }
+
+                let Libraries(libraries) = collager.libraries(&imager);
+                let index = collager.matched(&libraries)[0].index;
+
+                (index, Collager::lab_statistics(libraries[0].images[index].pixels()).0.l)
+            };
+
+            let (first, first_lightness) = frame(dark, None);
+            assert!((first_lightness - lab(dark).l).abs() < 0.01);
+
+            frame(second, Some(&[first])).1
+        };
+
+        assert!((picked(None) - lab(light).l).abs() < 0.01);
+        assert!((picked(Some(gain * 0.5)) - lab(light).l).abs() < 0.01);
+        assert!((picked(Some(gain * 2.0)) - lab(dark).l).abs() < 0.01);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn indexed_search()
+    {
+        // a plain lcg so the library is the same every run
+        let mut state = 12345_u32;
+        let mut random = move ||
+        {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+
+            (state >> 24) as u8
+        };
+
+        // noisy tiles around random base colors
+        let mut noisy = |width, height|
+        {
+            let base = [random(), random(), random()];
+
+            RgbImage::from_fn(width, height, |_, _|
+            {
+                Rgb(base.map(|value| value.saturating_add(random() / 4)))
+            })
+        };
+
+        let tile_size = Vec2{x: 6, y: 6};
+
+        let images = (0..300).map(|_|
+        {
+            LabImage::from_rgb(noisy(tile_size.x, tile_size.y), ColorSpace::Cielab)
+        }).collect::<Vec<_>>();
+
+        let target = noisy(tile_size.x * 4, tile_size.y);
+
+        let library = Library{
+            index: Some(TileIndex::new(images.iter())),
+            images: Arc::new(images),
+            rgb: None,
+            sources: (0..300).collect(),
+            sources_amount: 300
+        };
+
+        let fits = |search|
+        {
+            let collager = Collager::new(target.clone(), Config{
+                width: Some(4),
+                search,
+                ..config(tile_size)
+            });
+
+            collager.positions_iter().flat_map(|position|
+            {
+                collager.best_fits(&library, position, tile_size, 3, |_| true)
+            }).collect::<Vec<_>>()
+        };
+
+        let linear = fits(Search::Linear);
+        let indexed = fits(Search::Indexed{candidates: None});
+
+        assert_eq!(linear.len(), 12);
+        assert_eq!(indexed.len(), linear.len());
+
+        linear.iter().zip(indexed.iter()).for_each(|(linear, indexed)|
+        {
+            assert_eq!(linear.index, indexed.index);
+            assert!((linear.error - indexed.error).abs() <= linear.error * 0.0001);
+        });
+    }
+
+    // a library of flat colored tiles in its own directory
+    fn imager(name: &str, colors: &[Rgb<u8>], tile_size: Vec2) -> (Imager, PathBuf)
+    {
+        let directory = env::temp_dir().join(format!("collager-{name}-test-{}", process::id()));
+
+        fs::create_dir_all(&directory).unwrap();
+
+        colors.iter().enumerate().for_each(|(index, color)|
+        {
+            let path = directory.join(format!("{index}.png"));
+
+            RgbImage::from_pixel(4, 4, *color).save(path).unwrap();
+        });
+
+        let config = imager::Config{
+            image_size: tile_size,
+            allow_rotate: false,
+            allow_invert: false,
+            depth: 0,
+            space: ColorSpace::Cielab,
+            cache: None,
+            pool: WorkerPool::new(1)
+        };
+
+        (Imager::new(&directory, config).unwrap(), directory)
+    }
+
+    fn config(tile_size: Vec2) -> Config
+    {
+        Config{
+            width: Some(2),
+            height: Some(1),
+            tiles_total: None,
+            fit: FitMode::Fit,
+            background: Rgb([0, 0, 0]),
+            grout: Grout{gap: 0, margin: 0, radius: 0, color: None},
+            tile_size,
+            grid: Grid::Square,
+            metric: DistanceMetric::Cie76,
+            space: ColorSpace::Cielab,
+            search: Search::Linear,
+            reuse: None,
+            assignment: Assignment::Greedy,
+            correction: None,
+            render_size: None,
+            quadtree: None,
+            overlay: None,
+            diffusion: None,
+            switch_cost: None,
+            pool: WorkerPool::new(1),
+            manifest: false,
+            target_path: None
         }
     }
 }
@@ -1,5 +1,162 @@
+use std::{
+    fmt,
+    str::FromStr,
+    f32::consts::PI
+};
+
 use image::Rgb;
 
+use serde::{Serialize, Deserialize};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMetric
+{
+    Cie76,
+    Cie94,
+    Ciede2000
+}
+
+impl DistanceMetric
+{
+    // all of them return the squared difference, so they can be swapped freely
+    pub fn distance(self, reference: Lab, other: Lab) -> f32
+    {
+        match self
+        {
+            Self::Cie76 => reference.distance(other),
+            Self::Cie94 => reference.distance_cie94(other),
+            Self::Ciede2000 => reference.distance_ciede2000(other)
+        }
+    }
+}
+
+impl FromStr for DistanceMetric
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_lowercase().as_str()
+        {
+            "cie76" => Ok(Self::Cie76),
+            "cie94" => Ok(Self::Cie94),
+            "ciede2000" | "cie2000" => Ok(Self::Ciede2000),
+            x => Err(format!("unknown metric: {x} (expected cie76, cie94 or ciede2000)"))
+        }
+    }
+}
+
+impl fmt::Display for DistanceMetric
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Self::Cie76 => "cie76",
+            Self::Cie94 => "cie94",
+            Self::Ciede2000 => "ciede2000"
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace
+{
+    Cielab,
+    Oklab
+}
+
+impl ColorSpace
+{
+    pub fn from_rgb(self, value: Rgb<f32>) -> Lab
+    {
+        match self
+        {
+            Self::Cielab => Lab::from(value),
+            Self::Oklab => Oklab::from(value).into()
+        }
+    }
+
+    pub fn to_rgb(self, value: Lab) -> Rgb<f32>
+    {
+        match self
+        {
+            Self::Cielab => value.into(),
+            Self::Oklab => Oklab::from(value).into()
+        }
+    }
+}
+
+impl FromStr for ColorSpace
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_lowercase().as_str()
+        {
+            "cielab" | "lab" => Ok(Self::Cielab),
+            "oklab" => Ok(Self::Oklab),
+            x => Err(format!("unknown color space: {x} (expected cielab or oklab)"))
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Self::Cielab => "cielab",
+            Self::Oklab => "oklab"
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+// parses rrggbb hex (with or without a #) or r,g,b
+pub fn parse_rgb(s: &str) -> Result<Rgb<u8>, String>
+{
+    let invalid = || format!("invalid color: {s} (expected rrggbb or r,g,b)");
+
+    let s = s.trim();
+
+    let values = if s.contains(',')
+    {
+        s.split(',').map(|value| value.trim().parse::<u8>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?
+    } else
+    {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+
+        if hex.len() != 6 || !hex.is_ascii()
+        {
+            return Err(invalid());
+        }
+
+        (0..3).map(|index|
+        {
+            u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())
+        }).collect::<Result<Vec<_>, _>>()?
+    };
+
+    let values: [u8; 3] = values.try_into().map_err(|_| invalid())?;
+
+    Ok(Rgb::from(values))
+}
+
+pub fn format_rgb(color: Rgb<u8>) -> String
+{
+    let Rgb([r, g, b]) = color;
+
+    format!("{r:02x}{g:02x}{b:02x}")
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct Lab
@@ -11,6 +168,7 @@ pub struct Lab
 
 impl Lab
 {
+    // cie76, squared
     pub fn distance(&self, other: Lab) -> f32
     {
         let d_l = other.l - self.l;
@@ -19,6 +177,127 @@ impl Lab
 
         d_l.powi(2) + d_a.powi(2) + d_b.powi(2)
     }
+
+    // graphic arts weights, self is the reference color, squared
+    pub fn distance_cie94(&self, other: Lab) -> f32
+    {
+        let k_1 = 0.045;
+        let k_2 = 0.015;
+
+        let c_1 = self.a.hypot(self.b);
+        let c_2 = other.a.hypot(other.b);
+
+        let d_l = self.l - other.l;
+        let d_c = c_1 - c_2;
+
+        let d_a = self.a - other.a;
+        let d_b = self.b - other.b;
+
+        // can go slightly negative from rounding
+        let d_h_squared = (d_a.powi(2) + d_b.powi(2) - d_c.powi(2)).max(0.0);
+
+        let s_c = 1.0 + k_1 * c_1;
+        let s_h = 1.0 + k_2 * c_1;
+
+        d_l.powi(2) + (d_c / s_c).powi(2) + d_h_squared / s_h.powi(2)
+    }
+
+    // squared
+    pub fn distance_ciede2000(&self, other: Lab) -> f32
+    {
+        let hue = |a: f32, b: f32| -> f32
+        {
+            if a == 0.0 && b == 0.0
+            {
+                0.0
+            } else
+            {
+                b.atan2(a).rem_euclid(2.0 * PI)
+            }
+        };
+
+        let c_mean = (self.a.hypot(self.b) + other.a.hypot(other.b)) / 2.0;
+
+        let c_mean_7 = c_mean.powi(7);
+        let g = 0.5 * (1.0 - (c_mean_7 / (c_mean_7 + 25.0_f32.powi(7))).sqrt());
+
+        let a_1 = self.a * (1.0 + g);
+        let a_2 = other.a * (1.0 + g);
+
+        let c_1 = a_1.hypot(self.b);
+        let c_2 = a_2.hypot(other.b);
+
+        let h_1 = hue(a_1, self.b);
+        let h_2 = hue(a_2, other.b);
+
+        let d_l = other.l - self.l;
+        let d_c = c_2 - c_1;
+
+        let chroma_product = c_1 * c_2;
+
+        let d_h = if chroma_product == 0.0
+        {
+            0.0
+        } else
+        {
+            let d = h_2 - h_1;
+
+            if d > PI
+            {
+                d - 2.0 * PI
+            } else if d < -PI
+            {
+                d + 2.0 * PI
+            } else
+            {
+                d
+            }
+        };
+
+        let d_h_big = 2.0 * chroma_product.sqrt() * (d_h / 2.0).sin();
+
+        let l_mean = (self.l + other.l) / 2.0;
+        let c_mean = (c_1 + c_2) / 2.0;
+
+        let h_mean = if chroma_product == 0.0
+        {
+            h_1 + h_2
+        } else if (h_1 - h_2).abs() <= PI
+        {
+            (h_1 + h_2) / 2.0
+        } else if (h_1 + h_2) < 2.0 * PI
+        {
+            (h_1 + h_2 + 2.0 * PI) / 2.0
+        } else
+        {
+            (h_1 + h_2 - 2.0 * PI) / 2.0
+        };
+
+        let t = 1.0
+            - 0.17 * (h_mean - 30.0_f32.to_radians()).cos()
+            + 0.24 * (2.0 * h_mean).cos()
+            + 0.32 * (3.0 * h_mean + 6.0_f32.to_radians()).cos()
+            - 0.20 * (4.0 * h_mean - 63.0_f32.to_radians()).cos();
+
+        let d_theta = 30.0_f32.to_radians()
+            * (-((h_mean.to_degrees() - 275.0) / 25.0).powi(2)).exp();
+
+        let c_mean_7 = c_mean.powi(7);
+        let r_c = 2.0 * (c_mean_7 / (c_mean_7 + 25.0_f32.powi(7))).sqrt();
+
+        let l_offset = (l_mean - 50.0).powi(2);
+        let s_l = 1.0 + (0.015 * l_offset) / (20.0 + l_offset).sqrt();
+        let s_c = 1.0 + 0.045 * c_mean;
+        let s_h = 1.0 + 0.015 * c_mean * t;
+
+        let r_t = -(2.0 * d_theta).sin() * r_c;
+
+        let l_term = d_l / s_l;
+        let c_term = d_c / s_c;
+        let h_term = d_h_big / s_h;
+
+        l_term.powi(2) + c_term.powi(2) + h_term.powi(2) + r_t * c_term * h_term
+    }
 }
 
 impl From<Xyz> for Lab
@@ -60,29 +339,181 @@ impl From<Rgb<f32>> for Lab
     }
 }
 
+impl From<Lab> for Rgb<f32>
+{
+    fn from(value: Lab) -> Self
+    {
+        Xyz::from(value).into()
+    }
+}
+
+// oklab gets stored in the same triple as cielab, scaled up so the lightness
+// is also 0..100 and the errors end up in a similar range
+const OKLAB_SCALE: f32 = 100.0;
+
 #[derive(Debug, Clone, Copy)]
-struct Xyz
+pub struct Oklab
+{
+    pub l: f32,
+    pub a: f32,
+    pub b: f32
+}
+
+impl From<Oklab> for Lab
 {
-    x: f32,
-    y: f32,
-    z: f32
+    fn from(value: Oklab) -> Self
+    {
+        Self{
+            l: value.l * OKLAB_SCALE,
+            a: value.a * OKLAB_SCALE,
+            b: value.b * OKLAB_SCALE
+        }
+    }
 }
 
-impl From<Rgb<f32>> for Xyz
+impl From<Lab> for Oklab
 {
+    fn from(value: Lab) -> Self
+    {
+        Self{
+            l: value.l / OKLAB_SCALE,
+            a: value.a / OKLAB_SCALE,
+            b: value.b / OKLAB_SCALE
+        }
+    }
+}
+
+impl From<Rgb<f32>> for Oklab
+{
+    #[allow(clippy::excessive_precision)]
     fn from(value: Rgb<f32>) -> Self
     {
+        let r = srgb_to_linear(value.0[0]);
+        let g = srgb_to_linear(value.0[1]);
+        let b = srgb_to_linear(value.0[2]);
+
+        let l = 0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b;
+        let m = 0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b;
+        let s = 0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b;
+
+        let l = l.cbrt();
+        let m = m.cbrt();
+        let s = s.cbrt();
+
+        Self{
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s
+        }
+    }
+}
+
+impl From<Oklab> for Rgb<f32>
+{
+    #[allow(clippy::excessive_precision)]
+    fn from(value: Oklab) -> Self
+    {
+        let l = value.l + 0.3963377774 * value.a + 0.2158037573 * value.b;
+        let m = value.l - 0.1055613458 * value.a - 0.0638541728 * value.b;
+        let s = value.l - 0.0894841775 * value.a - 1.2914855480 * value.b;
+
+        let l = l.powi(3);
+        let m = m.powi(3);
+        let s = s.powi(3);
+
+        let r = 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s;
+        let g = -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s;
+        let b = -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s;
+
+        Rgb::from([linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)])
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32
+{
+    if value <= 0.0031308
+    {
+        value * 12.92
+    } else
+    {
+        value.powf(1.0 / 2.4) * 1.055 - 0.055
+    }
+}
+
+pub fn srgb_to_linear(value: f32) -> f32
+{
+    if value <= 0.04045
+    {
+        value / 12.92
+    } else
+    {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Xyz
+{
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+impl From<Lab> for Xyz
+{
+    fn from(value: Lab) -> Self
+    {
+        let delta = 6.0_f32 / 29.0;
+        let lower_scale = delta.powi(2) * 3.0;
+
         let f = |value: f32| -> f32
         {
-            let value = if value <= 0.04045
+            if value > delta
             {
-                value / 12.92
+                value.powi(3)
             } else
             {
-                ((value + 0.055) / 1.055).powf(2.4)
-            };
+                (value - (4.0 / 29.0)) * lower_scale
+            }
+        };
+
+        let y = (value.l + 16.0) / 116.0;
+        let x = y + value.a / 500.0;
+        let z = y - value.b / 200.0;
+
+        Self{
+            x: f(x) * 95.047,
+            y: f(y) * 100.0,
+            z: f(z) * 108.883
+        }
+    }
+}
+
+impl From<Xyz> for Rgb<f32>
+{
+    #[allow(clippy::excessive_precision)]
+    fn from(value: Xyz) -> Self
+    {
+        let x = value.x / 100.0;
+        let y = value.y / 100.0;
+        let z = value.z / 100.0;
+
+        let r = 3.2404542 * x - 1.5371385 * y - 0.4985314 * z;
+        let g = -0.9692660 * x + 1.8760108 * y + 0.0415560 * z;
+        let b = 0.0556434 * x - 0.2040259 * y + 1.0572252 * z;
+
+        Rgb::from([linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)])
+    }
+}
 
-            value * 100.0
+impl From<Rgb<f32>> for Xyz
+{
+    #[allow(clippy::excessive_precision)]
+    fn from(value: Rgb<f32>) -> Self
+    {
+        let f = |value: f32| -> f32
+        {
+            srgb_to_linear(value) * 100.0
         };
 
         let r = f(value.0[0]);
@@ -134,4 +565,125 @@ mod tests
         close_enough(xyz.y, 11.278);
         close_enough(xyz.z, 58.190);
     }
+
+    fn close_rgb(a: Rgb<f32>, b: Rgb<f32>)
+    {
+        a.0.into_iter().zip(b.0).for_each(|(a, b)| close_enough(a, b));
+    }
+
+    fn test_colors() -> impl Iterator<Item=Rgb<f32>>
+    {
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5, 0.2, 0.8],
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.02, 0.9, 0.4],
+            [0.3, 0.3, 0.3]
+        ].into_iter().map(Rgb::from)
+    }
+
+    #[test]
+    fn lab_to_xyz()
+    {
+        let lab = Lab{l: 4.516, a: -15.371, b: -5.086};
+
+        let xyz = Xyz::from(lab);
+
+        close_enough(xyz.x, 0.1);
+        close_enough(xyz.y, 0.5);
+        close_enough(xyz.z, 0.9);
+
+        let xyz = Xyz{x: 20.907, y: 11.278, z: 58.190};
+
+        close_rgb(Rgb::from(xyz), Rgb::from([0.5, 0.2, 0.8]));
+    }
+
+    #[test]
+    fn round_trips()
+    {
+        test_colors().for_each(|rgb|
+        {
+            close_rgb(Rgb::from(Xyz::from(rgb)), rgb);
+            close_rgb(Rgb::from(Lab::from(rgb)), rgb);
+            close_rgb(Rgb::from(Oklab::from(rgb)), rgb);
+
+            [ColorSpace::Cielab, ColorSpace::Oklab].into_iter().for_each(|space|
+            {
+                close_rgb(space.to_rgb(space.from_rgb(rgb)), rgb);
+            });
+        });
+    }
+
+    #[test]
+    fn rgb_to_oklab()
+    {
+        let oklab = Oklab::from(Rgb::from([1.0, 1.0, 1.0]));
+
+        close_enough(oklab.l, 1.0);
+        close_enough(oklab.a, 0.0);
+        close_enough(oklab.b, 0.0);
+
+        let oklab = Oklab::from(Rgb::from([1.0, 0.0, 0.0]));
+
+        close_enough(oklab.l, 0.628);
+        close_enough(oklab.a, 0.2249);
+        close_enough(oklab.b, 0.1258);
+
+        let lab = ColorSpace::Oklab.from_rgb(Rgb::from([0.0, 0.0, 1.0]));
+
+        close_enough(lab.l / OKLAB_SCALE, 0.452);
+        close_enough(lab.a / OKLAB_SCALE, -0.0325);
+        close_enough(lab.b / OKLAB_SCALE, -0.3115);
+    }
+
+    #[test]
+    fn metrics()
+    {
+        let close_delta = |metric: DistanceMetric, a: Lab, b: Lab, expected: f32|
+        {
+            close_enough(metric.distance(a, b).sqrt(), expected);
+        };
+
+        let a = Lab{l: 50.0, a: 2.6772, b: -79.7751};
+        let b = Lab{l: 50.0, a: 0.0, b: -82.7485};
+
+        close_delta(DistanceMetric::Cie76, a, b, 4.0010);
+        close_delta(DistanceMetric::Cie94, a, b, 1.3950);
+        close_delta(DistanceMetric::Ciede2000, a, b, 2.0425);
+
+        let a = Lab{l: 50.0, a: 0.0, b: 0.0};
+        let b = Lab{l: 50.0, a: -1.0, b: 2.0};
+
+        close_delta(DistanceMetric::Cie94, a, b, 2.2361);
+        close_delta(DistanceMetric::Ciede2000, a, b, 2.3669);
+
+        // reference pairs from sharma, wu and dalal
+        let a = Lab{l: 50.0, a: 2.5, b: 0.0};
+        let b = Lab{l: 73.0, a: 25.0, b: -18.0};
+
+        close_delta(DistanceMetric::Ciede2000, a, b, 27.1492);
+
+        let a = Lab{l: 2.0776, a: 0.0795, b: -1.1350};
+        let b = Lab{l: 0.9033, a: -0.0636, b: -0.5514};
+
+        close_delta(DistanceMetric::Ciede2000, a, b, 0.9082);
+    }
+
+    #[test]
+    fn parse_colors()
+    {
+        let color = Rgb::from([255, 16, 0]);
+
+        assert_eq!(parse_rgb("#ff1000"), Ok(color));
+        assert_eq!(parse_rgb("FF1000"), Ok(color));
+        assert_eq!(parse_rgb("255, 16, 0"), Ok(color));
+
+        assert!(parse_rgb("ff10").is_err());
+        assert!(parse_rgb("256,0,0").is_err());
+        assert!(parse_rgb("1,2").is_err());
+
+        assert_eq!(parse_rgb(&format_rgb(color)), Ok(color));
+    }
 }
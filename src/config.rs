@@ -1,20 +1,112 @@
 use std::{
+    io,
+    process,
     fmt::Display,
     path::PathBuf
 };
 
-use argparse::{ArgumentParser, StoreOption, StoreTrue, Store};
+use argparse::{ArgumentParser, FromCommandLine, StoreOption, StoreTrue, Store};
+
+use crate::{
+    collager::{Assignment, FitMode},
+    grid::Grid,
+    blend::BlendMode,
+    colors::{DistanceMetric, ColorSpace}
+};
+
+
+impl FromCommandLine for DistanceMetric
+{
+    fn from_argument(s: &str) -> Result<Self, String>
+    {
+        s.parse()
+    }
+}
+
+impl FromCommandLine for ColorSpace
+{
+    fn from_argument(s: &str) -> Result<Self, String>
+    {
+        s.parse()
+    }
+}
+
+impl FromCommandLine for Assignment
+{
+    fn from_argument(s: &str) -> Result<Self, String>
+    {
+        s.parse()
+    }
+}
+
+impl FromCommandLine for FitMode
+{
+    fn from_argument(s: &str) -> Result<Self, String>
+    {
+        s.parse()
+    }
+}
+
+impl FromCommandLine for BlendMode
+{
+    fn from_argument(s: &str) -> Result<Self, String>
+    {
+        s.parse()
+    }
+}
+
+impl FromCommandLine for Grid
+{
+    fn from_argument(s: &str) -> Result<Self, String>
+    {
+        s.parse()
+    }
+}
 
 
 pub struct Config
 {
     pub debug: bool,
     pub pixel_size: u32,
+    pub tile_height: u32,
+    pub render_size: u32,
+    pub grid: Grid,
+    pub quadtree_min_size: u32,
+    pub split_error: f32,
     pub allow_rotate: bool,
     pub allow_invert: bool,
-    pub output_indices: Option<PathBuf>,
+    pub metric: DistanceMetric,
+    pub space: ColorSpace,
+    pub index: bool,
+    pub candidates: usize,
+    pub max_uses: usize,
+    pub min_repeat_distance: f32,
+    pub assignment: Assignment,
+    pub correction: f32,
+    pub correct_deviation: bool,
+    pub diffusion: f32,
+    pub switch_cost: f32,
+    pub overlay: f32,
+    pub overlay_mode: BlendMode,
+    pub overlay_linear: bool,
+    pub manifest: Option<PathBuf>,
+    pub html: Option<PathBuf>,
+    pub svg: Option<PathBuf>,
+    pub svg_embed: bool,
+    pub dzi: Option<PathBuf>,
+    pub cache: Option<PathBuf>,
+    pub no_cache: bool,
     pub depth: u32,
-    pub width: u32,
+    pub jobs: usize,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub tiles_total: Option<u32>,
+    pub fit: FitMode,
+    pub background: String,
+    pub gap: u32,
+    pub gap_color: String,
+    pub margin: u32,
+    pub corner_radius: u32,
     pub output: String,
     pub directory: String,
     pub input: String
@@ -26,21 +118,62 @@ impl Config
     {
         let mut config = Self::default();
 
-        let s_description = Self::tell_default("small image size", config.pixel_size);
+        let s_description = Self::tell_default("small image width", config.pixel_size);
 
-        let w_description = Self::tell_default(
-           "amount of small images as width",
-            config.width
+        let f_description = Self::tell_default(
+            "what to do if the image doesnt match the grid (fit shrinks the grid, \
+            fill crops the image, pad adds background around it)",
+            config.fit
         );
 
-        let o_description = Self::tell_default("output image name", &config.output);
+        let background_description = Self::tell_default(
+            "background color as rrggbb or r,g,b",
+            &config.background
+        );
+
+        let o_description = Self::tell_default(
+            "output image name, or directory for a directory of frames",
+            &config.output
+        );
 
         // will probably crash ur pc, the formula for how many images there will (roughly) be
         // is (1..depth).map(|d| t.pow(d)).sum()
         // where t is how many transparent images u have
         let d_description = Self::tell_default(
             "max permutation depth for transparent images",
-            &config.depth
+            config.depth
+        );
+
+        let m_description = Self::tell_default(
+            "color difference metric (cie76, cie94 or ciede2000)",
+            config.metric
+        );
+
+        let a_description = Self::tell_default(
+            "how tiles get assigned to cells (greedy, optimal or approximate), the last two \
+            use every image at most --max-uses times (1 if not set)",
+            config.assignment
+        );
+
+        let g_description = Self::tell_default(
+            "layout of the cells (square, hex-pointy or hex-flat)",
+            config.grid
+        );
+
+        let e_description = Self::tell_default(
+            "with --quadtree, split cells whose best image has a bigger average color distance",
+            config.split_error
+        );
+
+        let overlay_description = Self::tell_default(
+            "how the target gets blended over the collage (normal, soft-light, multiply \
+            or luminosity)",
+            config.overlay_mode
+        );
+
+        let c_description = Self::tell_default(
+            "color space to match in (cielab or oklab, oklab only with cie76)",
+            config.space
         );
 
         {
@@ -55,17 +188,223 @@ impl Config
             parser.refer(&mut config.allow_invert)
                 .add_option(&["-I", "--invert"], StoreTrue, "allow inverting the images");
 
-            parser.refer(&mut config.output_indices)
-                .add_option(&["-N", "--names"], StoreOption, "output image names in the collage");
+            parser.refer(&mut config.metric)
+                .add_option(&["-m", "--metric"], Store, &m_description);
+
+            parser.refer(&mut config.space)
+                .add_option(&["-c", "--space"], Store, &c_description);
+
+            parser.refer(&mut config.index)
+                .add_option(
+                    &["-x", "--index"],
+                    StoreTrue,
+                    "search the images with a k-d tree instead of comparing with every one"
+                );
+
+            parser.refer(&mut config.candidates)
+                .add_option(
+                    &["--candidates"],
+                    Store,
+                    "with --index, only compare this many closest images, 0 for exact (default 0)"
+                );
+
+            parser.refer(&mut config.max_uses)
+                .add_option(
+                    &["--max-uses"],
+                    Store,
+                    "max times the same image can be used, 0 for no limit (default 0)"
+                );
+
+            parser.refer(&mut config.min_repeat_distance)
+                .add_option(
+                    &["--min-repeat-distance"],
+                    Store,
+                    "min distance in cells between uses of the same image (default 0)"
+                );
+
+            parser.refer(&mut config.assignment)
+                .add_option(&["-a", "--assignment"], Store, &a_description);
+
+            parser.refer(&mut config.correction)
+                .add_option(
+                    &["-C", "--correct"],
+                    Store,
+                    "strength (0 to 1) of shifting tile colors towards the target (default 0)"
+                );
+
+            parser.refer(&mut config.correct_deviation)
+                .add_option(
+                    &["--correct-contrast"],
+                    StoreTrue,
+                    "also match the tiles color spread to the target when correcting"
+                );
+
+            parser.refer(&mut config.diffusion)
+                .add_option(
+                    &["--diffusion"],
+                    Store,
+                    "strength (0 to 1) of spreading each cells color error onto the next ones, \
+                    only with greedy assignment (default 0)"
+                );
+
+            parser.refer(&mut config.switch_cost)
+                .add_option(
+                    &["--switch-cost"],
+                    Store,
+                    "error per pixel a cell has to gain before it changes its tile between frames \
+                    of an animation or a directory of numbered frames (default 0)"
+                );
+
+            parser.refer(&mut config.overlay)
+                .add_option(
+                    &["--overlay"],
+                    Store,
+                    "opacity (0 to 1) of the target blended over the finished collage (default 0)"
+                );
+
+            parser.refer(&mut config.overlay_mode)
+                .add_option(&["--overlay-mode"], Store, &overlay_description);
+
+            parser.refer(&mut config.overlay_linear)
+                .add_option(&["--overlay-linear"], StoreTrue, "blend the overlay in linear light");
+
+            parser.refer(&mut config.manifest)
+                .add_option(
+                    &["-N", "--manifest", "--names"],
+                    StoreOption,
+                    "write where every tile went and where it came from, as csv if the file \
+                    ends with .csv and json otherwise"
+                );
+
+            parser.refer(&mut config.html)
+                .add_option(
+                    &["--html"],
+                    StoreOption,
+                    "write a page showing the collage where every tile links to its original"
+                );
+
+            parser.refer(&mut config.svg)
+                .add_option(
+                    &["--svg"],
+                    StoreOption,
+                    "write the collage as an svg with an image element for every tile"
+                );
+
+            parser.refer(&mut config.svg_embed)
+                .add_option(
+                    &["--svg-embed"],
+                    StoreTrue,
+                    "embed the tiles in the svg instead of linking to their files"
+                );
+
+            parser.refer(&mut config.dzi)
+                .add_option(
+                    &["--dzi"],
+                    StoreOption,
+                    "write a deep zoom tile pyramid instead of a single image, for collages \
+                    too big to fit in memory"
+                );
+
+            parser.refer(&mut config.cache)
+                .add_option(
+                    &["--cache"],
+                    StoreOption,
+                    "cache file for the resized images so the next run doesnt have to decode \
+                    them (default library.collager-cache next to the directory)"
+                );
+
+            parser.refer(&mut config.no_cache)
+                .add_option(
+                    &["--no-cache"],
+                    StoreTrue,
+                    "dont read or write the cache file"
+                );
 
             parser.refer(&mut config.depth)
                 .add_option(&["-D", "--depth"], Store, &d_description);
 
+            parser.refer(&mut config.jobs)
+                .add_option(
+                    &["-j", "--jobs"],
+                    Store,
+                    "amount of worker threads, 0 for one per cpu (default 0)"
+                );
+
             parser.refer(&mut config.pixel_size)
                 .add_option(&["-s", "--size"], Store, &s_description);
 
+            parser.refer(&mut config.tile_height)
+                .add_option(
+                    &["--tile-height"],
+                    Store,
+                    "small image height, 0 for square images (default 0)"
+                );
+
+            parser.refer(&mut config.render_size)
+                .add_option(
+                    &["-R", "--render-size"],
+                    Store,
+                    "width of each image in the output, the height keeps the aspect ratio, \
+                    0 to use the matching size (default 0)"
+                );
+
+            parser.refer(&mut config.grid)
+                .add_option(&["-g", "--grid"], Store, &g_description);
+
+            parser.refer(&mut config.quadtree_min_size)
+                .add_option(
+                    &["-Q", "--quadtree"],
+                    Store,
+                    "split badly matching cells into four down to this size, 0 to not split \
+                    (default 0)"
+                );
+
+            parser.refer(&mut config.split_error)
+                .add_option(&["--split-error"], Store, &e_description);
+
             parser.refer(&mut config.width)
-                .add_option(&["-w", "--width"], Store, &w_description);
+                .add_option(
+                    &["-w", "--width"],
+                    StoreOption,
+                    "amount of small images as width (default 16 if no other size is set)"
+                );
+
+            parser.refer(&mut config.height)
+                .add_option(&["--height"], StoreOption, "amount of small images as height");
+
+            parser.refer(&mut config.tiles_total)
+                .add_option(
+                    &["--tiles-total"],
+                    StoreOption,
+                    "roughly how many small images in total, instead of width and height"
+                );
+
+            parser.refer(&mut config.fit)
+                .add_option(&["-f", "--fit"], Store, &f_description);
+
+            parser.refer(&mut config.background)
+                .add_option(&["--background"], Store, &background_description);
+
+            parser.refer(&mut config.gap)
+                .add_option(&["--gap"], Store, "pixels between the output tiles (default 0)");
+
+            parser.refer(&mut config.gap_color)
+                .add_option(
+                    &["--gap-color"],
+                    Store,
+                    "color between and around the tiles as rrggbb, r,g,b or transparent \
+                    (default same as --background)"
+                );
+
+            parser.refer(&mut config.margin)
+                .add_option(&["--margin"], Store, "pixels around the whole collage (default 0)");
+
+            parser.refer(&mut config.corner_radius)
+                .add_option(
+                    &["--corner-radius"],
+                    Store,
+                    "radius in pixels of the rounded tile corners (default 0)"
+                );
 
             parser.refer(&mut config.output)
                 .add_option(&["-o", "--output"], Store, &o_description);
@@ -76,8 +415,12 @@ impl Config
                 .required();
 
             parser.refer(&mut config.input)
-                .add_option(&["-i", "--input"], Store, "input image to collage")
-                .add_argument("input", Store, "input image to collage")
+                .add_option(
+                    &["-i", "--input"],
+                    Store,
+                    "input image or directory of frames to collage"
+                )
+                .add_argument("input", Store, "input image or directory of frames to collage")
                 .required();
 
             parser.parse_args_or_exit();
@@ -99,14 +442,222 @@ impl Default for Config
         Self{
             debug: false,
             pixel_size: 16,
+            tile_height: 0,
+            render_size: 0,
+            grid: Grid::Square,
+            quadtree_min_size: 0,
+            split_error: 8.0,
             allow_rotate: false,
             allow_invert: false,
-            output_indices: None,
+            metric: DistanceMetric::Cie76,
+            space: ColorSpace::Cielab,
+            index: false,
+            candidates: 0,
+            max_uses: 0,
+            min_repeat_distance: 0.0,
+            assignment: Assignment::Greedy,
+            correction: 0.0,
+            correct_deviation: false,
+            diffusion: 0.0,
+            switch_cost: 0.0,
+            overlay: 0.0,
+            overlay_mode: BlendMode::Normal,
+            overlay_linear: false,
+            manifest: None,
+            html: None,
+            svg: None,
+            svg_embed: false,
+            dzi: None,
+            cache: None,
+            no_cache: false,
             depth: 0,
-            width: 16,
+            jobs: 0,
+            width: None,
+            height: None,
+            tiles_total: None,
+            fit: FitMode::Fit,
+            background: "000000".to_owned(),
+            gap: 0,
+            gap_color: String::new(),
+            margin: 0,
+            corner_radius: 0,
             output: "output.png".to_owned(),
             directory: String::new(),
             input: String::new()
         }
     }
 }
+
+// options for drawing a collage again from its manifest, anything not set is taken from it
+pub struct RenderConfig
+{
+    pub render_size: Option<u32>,
+    pub gap: Option<u32>,
+    pub margin: Option<u32>,
+    pub corner_radius: Option<u32>,
+    pub gap_color: Option<String>,
+    pub overlay: Option<f32>,
+    pub overlay_mode: Option<BlendMode>,
+    pub overlay_linear: bool,
+    pub space: Option<ColorSpace>,
+    pub jobs: usize,
+    pub html: Option<PathBuf>,
+    pub svg: Option<PathBuf>,
+    pub svg_embed: bool,
+    pub dzi: Option<PathBuf>,
+    pub output: String,
+    pub manifest: String
+}
+
+impl RenderConfig
+{
+    // args without the render subcommand itself
+    pub fn parse(args: Vec<String>) -> Self
+    {
+        let mut config = Self::default();
+
+        let o_description = Config::tell_default("output image name", &config.output);
+
+        {
+            let mut parser = ArgumentParser::new();
+
+            parser.set_description(
+                "draw a collage again from its json manifest without matching, the color \
+                correction and the overlay are the ones the collage was made with"
+            );
+
+            parser.refer(&mut config.render_size)
+                .add_option(
+                    &["-R", "--render-size"],
+                    StoreOption,
+                    "width of each image in the output, the height keeps the aspect ratio"
+                );
+
+            parser.refer(&mut config.gap)
+                .add_option(&["--gap"], StoreOption, "pixels between neighbouring tiles");
+
+            parser.refer(&mut config.margin)
+                .add_option(&["--margin"], StoreOption, "pixels around the whole collage");
+
+            parser.refer(&mut config.corner_radius)
+                .add_option(
+                    &["--corner-radius"],
+                    StoreOption,
+                    "radius in pixels of the rounded tile corners"
+                );
+
+            parser.refer(&mut config.gap_color)
+                .add_option(
+                    &["--gap-color"],
+                    StoreOption,
+                    "color between and around the tiles as rrggbb, r,g,b or transparent"
+                );
+
+            parser.refer(&mut config.overlay)
+                .add_option(
+                    &["--overlay"],
+                    StoreOption,
+                    "opacity (0 to 1) of the target blended over the finished collage, 0 turns \
+                    it off (default is the overlay the collage was made with)"
+                );
+
+            parser.refer(&mut config.overlay_mode)
+                .add_option(
+                    &["--overlay-mode"],
+                    StoreOption,
+                    "how the target gets blended over the collage (normal, soft-light, multiply \
+                    or luminosity), also changes the stored overlay (default normal)"
+                );
+
+            parser.refer(&mut config.overlay_linear)
+                .add_option(
+                    &["--overlay-linear"],
+                    StoreTrue,
+                    "blend the overlay in linear light, also changes the stored overlay"
+                );
+
+            parser.refer(&mut config.space)
+                .add_option(
+                    &["-c", "--space"],
+                    StoreOption,
+                    "color space the luminosity overlay and the color correction work in \
+                    (cielab or oklab, default is the one the collage was made with)"
+                );
+
+            parser.refer(&mut config.jobs)
+                .add_option(
+                    &["-j", "--jobs"],
+                    Store,
+                    "amount of worker threads, 0 for one per cpu (default 0)"
+                );
+
+            parser.refer(&mut config.html)
+                .add_option(
+                    &["--html"],
+                    StoreOption,
+                    "write a page showing the collage where every tile links to its original"
+                );
+
+            parser.refer(&mut config.svg)
+                .add_option(
+                    &["--svg"],
+                    StoreOption,
+                    "write the collage as an svg with an image element for every tile"
+                );
+
+            parser.refer(&mut config.svg_embed)
+                .add_option(
+                    &["--svg-embed"],
+                    StoreTrue,
+                    "embed the tiles in the svg instead of linking to their files"
+                );
+
+            parser.refer(&mut config.dzi)
+                .add_option(
+                    &["--dzi"],
+                    StoreOption,
+                    "write a deep zoom tile pyramid instead of a single image, for collages \
+                    too big to fit in memory"
+                );
+
+            parser.refer(&mut config.output)
+                .add_option(&["-o", "--output"], Store, &o_description);
+
+            parser.refer(&mut config.manifest)
+                .add_argument("manifest", Store, "json manifest written with --manifest")
+                .required();
+
+            if let Err(code) = parser.parse(args, &mut io::stdout(), &mut io::stderr())
+            {
+                process::exit(code);
+            }
+        }
+
+        config
+    }
+}
+
+impl Default for RenderConfig
+{
+    fn default() -> Self
+    {
+        Self{
+            render_size: None,
+            gap: None,
+            margin: None,
+            corner_radius: None,
+            gap_color: None,
+            overlay: None,
+            overlay_mode: None,
+            overlay_linear: false,
+            space: None,
+            jobs: 0,
+            html: None,
+            svg: None,
+            svg_embed: false,
+            dzi: None,
+            output: "output.png".to_owned(),
+            manifest: String::new()
+        }
+    }
+}
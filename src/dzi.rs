@@ -0,0 +1,404 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    collections::{HashMap, HashSet}
+};
+
+use image::{
+    Rgba,
+    Pixel,
+    RgbImage,
+    RgbaImage,
+    DynamicImage,
+    imageops::{self, FilterType}
+};
+
+use crate::{
+    Vec2,
+    colors,
+    collager::Grout,
+    imager::{self, TileSource},
+    manifest::{Manifest, ManifestCell},
+    pool::WorkerPool
+};
+
+
+// size of the square tiles every level gets cut into
+const TILE_SIZE: u32 = 256;
+
+// a deep zoom pyramid, the full size level gets drawn a row of tiles at a time straight
+// from the tile files and every level above it is made from the one below read back from disk
+pub fn save(path: &Path, manifest: &Manifest, pool: WorkerPool) -> Result<(), imager::Error>
+{
+    let size = Vec2{x: manifest.width.max(1), y: manifest.height.max(1)};
+    let top = max_level(size);
+
+    let files = files_directory(path);
+
+    for level in 0..=top
+    {
+        fs::create_dir_all(files.join(level.to_string()))?;
+    }
+
+    draw_level(&files.join(top.to_string()), manifest, pool)?;
+
+    for level in (0..top).rev()
+    {
+        let directory = files.join(level.to_string());
+        let children = files.join((level + 1).to_string());
+
+        shrink_level(&directory, &children, level_size(size, top, level), pool)?;
+    }
+
+    let descriptor = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" \
+Format=\"png\" Overlap=\"0\" TileSize=\"{TILE_SIZE}\">
+<Size Width=\"{}\" Height=\"{}\"/>
+</Image>
+",
+        size.x,
+        size.y
+    );
+
+    fs::write(path, descriptor)?;
+
+    Ok(())
+}
+
+// name.dzi keeps its tiles in name_files
+fn files_directory(path: &Path) -> PathBuf
+{
+    let mut name = path.with_extension("").into_os_string();
+    name.push("_files");
+
+    PathBuf::from(name)
+}
+
+// the level where every pixel is the full image, level 0 is a single pixel
+fn max_level(size: Vec2) -> u32
+{
+    (size.x.max(size.y).max(1) as u64).next_power_of_two().trailing_zeros()
+}
+
+// every level is half the size of the next one, rounded up
+fn level_size(size: Vec2, top: u32, level: u32) -> Vec2
+{
+    let scale = 1_u64 << (top - level);
+    let shrink = |value: u32| (value as u64).div_ceil(scale) as u32;
+
+    Vec2{x: shrink(size.x), y: shrink(size.y)}
+}
+
+fn tile_path(directory: &Path, column: u32, row: u32) -> PathBuf
+{
+    directory.join(format!("{column}_{row}.png"))
+}
+
+fn draw_level(directory: &Path, manifest: &Manifest, pool: WorkerPool) -> Result<(), imager::Error>
+{
+    let size = Vec2{x: manifest.width.max(1), y: manifest.height.max(1)};
+
+    let grout = Grout{
+        gap: manifest.gap,
+        margin: manifest.margin,
+        radius: manifest.corner_radius,
+        color: manifest.gap_color.as_ref().and_then(|color| colors::parse_rgb(color).ok())
+    };
+
+    let background = grout.color.map(|color| color.to_rgba()).unwrap_or(Rgba([0, 0, 0, 0]));
+
+    // only the hex grids have masks and their tiles are all the same size
+    let mask = manifest.grid.mask(Vec2{x: manifest.render_width, y: manifest.render_height});
+
+    let columns = size.x.div_ceil(TILE_SIZE);
+
+    let mut loaded: HashMap<(TileSource, Vec2), RgbImage> = HashMap::new();
+
+    for row in 0..size.y.div_ceil(TILE_SIZE)
+    {
+        let top = row * TILE_SIZE;
+        let bottom = (top + TILE_SIZE).min(size.y);
+
+        let cells = manifest.cells.iter().filter(|cell|
+        {
+            cell.y < bottom && top < cell.y + cell.height
+        }).map(|cell| (cell, (cell.tile_source(), cell_size(cell)))).collect::<Vec<_>>();
+
+        // every tile gets decoded once, even if its cell spans several strips
+        let missing = cells.iter().map(|(_, key)| key).filter(|key| !loaded.contains_key(key))
+            .cloned().collect::<HashSet<_>>().into_iter().collect::<Vec<_>>();
+
+        let images = pool.map(missing.iter(), |(source, size)|
+        {
+            source.load(*size).map(|image|
+            {
+                // turned tiles come out the other way around if the cell isnt square
+                if image.dimensions() != (size.x, size.y)
+                {
+                    imageops::resize(&image, size.x, size.y, FilterType::CatmullRom)
+                } else
+                {
+                    image
+                }
+            })
+        }).into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        loaded.extend(missing.into_iter().zip(images));
+
+        let saved = pool.map(0..columns, |column|
+        {
+            let left = column * TILE_SIZE;
+            let right = (left + TILE_SIZE).min(size.x);
+
+            let mut tile = RgbaImage::from_pixel(right - left, bottom - top, background);
+
+            cells.iter().filter(|(cell, _)| cell.x < right && left < cell.x + cell.width)
+                .for_each(|(cell, key)|
+                {
+                    let image = &loaded[key];
+                    let cell_size = cell_size(cell);
+
+                    for y in cell.y.max(top)..(cell.y + cell_size.y).min(bottom)
+                    {
+                        for x in cell.x.max(left)..(cell.x + cell_size.x).min(right)
+                        {
+                            let (local_x, local_y) = (x - cell.x, y - cell.y);
+
+                            let inside = mask.as_ref().map(|mask|
+                            {
+                                mask.get((local_y * cell_size.x + local_x) as usize)
+                                    .copied().unwrap_or(true)
+                            }).unwrap_or(true);
+
+                            if inside && grout.covers(local_x, local_y, cell_size)
+                            {
+                                let pixel = image.get_pixel(local_x, local_y).to_rgba();
+
+                                tile.put_pixel(x - left, y - top, pixel);
+                            }
+                        }
+                    }
+                });
+
+            save_tile(tile_path(directory, column, row), tile, grout.color.is_none())
+        });
+
+        saved.into_iter().collect::<Result<(), _>>()?;
+
+        // only the tiles that go past this strip are needed again
+        let kept = cells.iter().filter(|(cell, _)| cell.y + cell.height > bottom)
+            .map(|(_, key)| key).collect::<HashSet<_>>();
+
+        loaded.retain(|key, _| kept.contains(key));
+    }
+
+    Ok(())
+}
+
+// each tile is its four children squashed to half size
+fn shrink_level(
+    directory: &Path,
+    children: &Path,
+    size: Vec2,
+    pool: WorkerPool
+) -> Result<(), imager::Error>
+{
+    let tiles = (0..size.y.div_ceil(TILE_SIZE)).flat_map(|row|
+    {
+        (0..size.x.div_ceil(TILE_SIZE)).map(move |column| (column, row))
+    });
+
+    let saved = pool.map(tiles, |(column, row)|
+    {
+        let mut combined: Option<(RgbaImage, bool)> = None;
+
+        let mut width = 0;
+        let mut height = 0;
+
+        let children = [(0, 0), (1, 0), (0, 1), (1, 1)].into_iter().map(|(x, y)|
+        {
+            let path = tile_path(children, column * 2 + x, row * 2 + y);
+
+            // edge tiles dont always have all four children
+            if !path.exists()
+            {
+                return Ok(None);
+            }
+
+            let child = image::open(&path).map_err(|err| imager::Error::new(&path, err))?;
+
+            Ok(Some(((x, y), child)))
+        }).collect::<Result<Vec<_>, imager::Error>>()?;
+
+        children.iter().flatten().for_each(|((x, y), child)|
+        {
+            width = width.max(x * TILE_SIZE + child.width());
+            height = height.max(y * TILE_SIZE + child.height());
+        });
+
+        children.into_iter().flatten().for_each(|((x, y), child)|
+        {
+            let (image, transparent) = combined.get_or_insert_with(||
+            {
+                (RgbaImage::new(width.max(1), height.max(1)), false)
+            });
+
+            *transparent |= matches!(child, DynamicImage::ImageRgba8(_));
+
+            let (x, y) = ((x * TILE_SIZE) as i64, (y * TILE_SIZE) as i64);
+            imageops::replace(image, &child.into_rgba8(), x, y);
+        });
+
+        let (image, transparent) = combined.unwrap_or_else(|| (RgbaImage::new(1, 1), true));
+
+        let shrunk = imageops::resize(
+            &image,
+            image.width().div_ceil(2),
+            image.height().div_ceil(2),
+            FilterType::Triangle
+        );
+
+        save_tile(tile_path(directory, column, row), shrunk, transparent)
+    });
+
+    saved.into_iter().collect()
+}
+
+fn cell_size(cell: &ManifestCell) -> Vec2
+{
+    Vec2{x: cell.width.max(1), y: cell.height.max(1)}
+}
+
+fn save_tile(path: PathBuf, tile: RgbaImage, transparent: bool) -> Result<(), imager::Error>
+{
+    let image = if transparent
+    {
+        DynamicImage::ImageRgba8(tile)
+    } else
+    {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(tile).into_rgb8())
+    };
+
+    image.save(&path).map_err(|err| imager::Error::new(path, err))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use std::{env, process};
+
+    use image::Rgb;
+
+    use crate::{
+        collager::FitMode,
+        colors::ColorSpace,
+        grid::Grid,
+        imager::Transform
+    };
+
+
+    #[test]
+    fn levels()
+    {
+        let size = Vec2{x: 1000, y: 300};
+
+        let top = max_level(size);
+        assert_eq!(top, 10);
+
+        assert_eq!(level_size(size, top, top), size);
+        assert_eq!(level_size(size, top, top - 1), Vec2{x: 500, y: 150});
+        assert_eq!(level_size(size, top, top - 3), Vec2{x: 125, y: 38});
+        assert_eq!(level_size(size, top, 0), Vec2{x: 1, y: 1});
+
+        assert_eq!(max_level(Vec2{x: 1, y: 1}), 0);
+        assert_eq!(max_level(Vec2{x: 256, y: 2}), 8);
+
+        assert_eq!(files_directory(Path::new("out/big.dzi")), PathBuf::from("out/big_files"));
+    }
+
+    #[test]
+    fn pyramid()
+    {
+        let directory = env::temp_dir().join(format!("collager-dzi-test-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let colors = [Rgb([255, 0, 0]), Rgb([0, 0, 255])];
+
+        // two cells side by side, the second one going over the first tile edge
+        let cells = colors.iter().enumerate().map(|(index, color)|
+        {
+            let source = directory.join(format!("tile{index}.png"));
+            RgbImage::from_pixel(4, 4, *color).save(&source).unwrap();
+
+            ManifestCell{
+                column: index as u32,
+                row: 0,
+                level: 0,
+                target_x: index as u32 * 4,
+                target_y: 0,
+                target_width: 4,
+                target_height: 4,
+                x: index as u32 * 150,
+                y: 0,
+                width: 150,
+                height: 300,
+                source,
+                transform: Transform::default(),
+                overlays: Vec::new(),
+                error: 0.0
+            }
+        }).collect();
+
+        let manifest = Manifest{
+            target: None,
+            grid: Grid::Square,
+            columns: 2,
+            rows: 1,
+            fit: FitMode::Fit,
+            background: "000000".to_owned(),
+            tile_width: 4,
+            tile_height: 4,
+            render_width: 150,
+            render_height: 300,
+            width: 300,
+            height: 300,
+            gap: 0,
+            margin: 0,
+            corner_radius: 0,
+            gap_color: Some("000000".to_owned()),
+            space: ColorSpace::Cielab,
+            correction: None,
+            overlay: None,
+            cells
+        };
+
+        let path = directory.join("collage.dzi");
+        save(&path, &manifest, WorkerPool::new(1)).unwrap();
+
+        let files = directory.join("collage_files");
+
+        let tile = |level: u32, column: u32, row: u32|
+        {
+            let path = tile_path(&files.join(level.to_string()), column, row);
+
+            image::open(path).unwrap().into_rgb8()
+        };
+
+        let top = max_level(Vec2{x: 300, y: 300});
+
+        assert_eq!(tile(top, 0, 0).dimensions(), (256, 256));
+        assert_eq!(tile(top, 1, 1).dimensions(), (44, 44));
+
+        assert_eq!(*tile(top, 0, 1).get_pixel(10, 10), colors[0]);
+        assert_eq!(*tile(top, 0, 1).get_pixel(200, 10), colors[1]);
+        assert_eq!(*tile(top, 1, 0).get_pixel(10, 10), colors[1]);
+
+        assert_eq!(tile(top - 1, 0, 0).dimensions(), (150, 150));
+        assert_eq!(tile(0, 0, 0).dimensions(), (1, 1));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
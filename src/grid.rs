@@ -0,0 +1,256 @@
+use std::{
+    fmt,
+    str::FromStr
+};
+
+use serde::{Serialize, Deserialize};
+
+use crate::Vec2;
+
+
+// neighbouring hexes overlap by this much so rounding never leaves gaps between them
+const HEX_SLACK: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Grid
+{
+    Square,
+    // every other row is shifted by half a cell and rows overlap by a quarter
+    HexPointy,
+    // same thing but with columns
+    HexFlat
+}
+
+impl FromStr for Grid
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_lowercase().as_str()
+        {
+            "square" => Ok(Self::Square),
+            "hex" | "pointy" | "hex-pointy" => Ok(Self::HexPointy),
+            "flat" | "hex-flat" => Ok(Self::HexFlat),
+            x => Err(format!("unknown grid: {x} (expected square, hex-pointy or hex-flat)"))
+        }
+    }
+}
+
+impl fmt::Display for Grid
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Self::Square => "square",
+            Self::HexPointy => "hex-pointy",
+            Self::HexFlat => "hex-flat"
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl Grid
+{
+    // width in pixels of columns cells
+    pub fn width(self, columns: u32, size: Vec2) -> u32
+    {
+        match self
+        {
+            Self::Square => columns * size.x,
+            Self::HexPointy => columns * size.x + size.x / 2,
+            Self::HexFlat => Self::overlapped(columns, size.x)
+        }
+    }
+
+    // amount of columns that comes closest to width pixels, at least one
+    pub fn columns(self, width: u32, size: Vec2) -> u32
+    {
+        match self
+        {
+            Self::Square => Self::closest(width, 0, size.x),
+            Self::HexPointy => Self::closest(width, size.x / 2, size.x),
+            Self::HexFlat => Self::closest_overlapped(width, size.x)
+        }
+    }
+
+    // amount of rows that comes closest to height pixels, at least one
+    pub fn rows(self, height: u32, size: Vec2) -> u32
+    {
+        match self
+        {
+            Self::Square => Self::closest(height, 0, size.y),
+            Self::HexPointy => Self::closest_overlapped(height, size.y),
+            Self::HexFlat => Self::closest(height, size.y / 2, size.y)
+        }
+    }
+
+    // size in pixels of the whole grid
+    pub fn size(self, columns: u32, rows: u32, size: Vec2) -> Vec2
+    {
+        let height = match self
+        {
+            Self::Square => rows * size.y,
+            Self::HexPointy => Self::overlapped(rows, size.y),
+            Self::HexFlat => rows * size.y + size.y / 2
+        };
+
+        Vec2{x: self.width(columns, size), y: height}
+    }
+
+    // top left corner of the cells bounding box
+    pub fn position(self, cell: Vec2, size: Vec2) -> Vec2
+    {
+        let half = |odd: u32, size: u32| if odd % 2 == 1 { size / 2 } else { 0 };
+        let step = |value: u32, size: u32| (value as f32 * size as f32 * 0.75).round() as u32;
+
+        match self
+        {
+            Self::Square => Vec2{x: cell.x * size.x, y: cell.y * size.y},
+            Self::HexPointy => Vec2{
+                x: cell.x * size.x + half(cell.y, size.x),
+                y: step(cell.y, size.y)
+            },
+            Self::HexFlat => Vec2{
+                x: step(cell.x, size.x),
+                y: cell.y * size.y + half(cell.x, size.y)
+            }
+        }
+    }
+
+    // which pixels of a cell belong to it, row major, none if all of them do
+    pub fn mask(self, size: Vec2) -> Option<Vec<bool>>
+    {
+        let center_x = size.x as f32 / 2.0;
+        let center_y = size.y as f32 / 2.0;
+
+        let inside = |x: u32, y: u32|
+        {
+            let dx = (x as f32 + 0.5 - center_x).abs();
+            let dy = (y as f32 + 0.5 - center_y).abs();
+
+            // the hex gets stretched to fill the cells bounding box
+            let ((across, across_center), (along, along_center)) = match self
+            {
+                Self::HexPointy => ((dx, center_x), (dy, center_y)),
+                Self::HexFlat => ((dy, center_y), (dx, center_x)),
+                Self::Square => unreachable!()
+            };
+
+            along / along_center + across / across_center / 2.0
+                <= 1.0 + HEX_SLACK / along_center
+        };
+
+        match self
+        {
+            Self::Square => None,
+            Self::HexPointy | Self::HexFlat =>
+            {
+                let mask = (0..size.y).flat_map(|y| (0..size.x).map(move |x| (x, y)))
+                    .map(|(x, y)| inside(x, y))
+                    .collect();
+
+                Some(mask)
+            }
+        }
+    }
+
+    fn closest(length: u32, extra: u32, size: u32) -> u32
+    {
+        let cells = length.saturating_sub(extra) as f32 / size.max(1) as f32;
+
+        (cells.round() as u32).max(1)
+    }
+
+    fn closest_overlapped(length: u32, size: u32) -> u32
+    {
+        let step = size.max(1) as f32 * 0.75;
+        let cells = length.saturating_sub(size) as f32 / step;
+
+        (cells.round() as u32) + 1
+    }
+
+    // size of cells many cells that overlap their neighbours by a quarter
+    fn overlapped(cells: u32, size: u32) -> u32
+    {
+        if cells == 0
+        {
+            return 0;
+        }
+
+        (((cells - 1) as f32 * size as f32 * 0.75).round() as u32) + size
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn closest_cells()
+    {
+        let size = Vec2{x: 10, y: 10};
+
+        [Grid::Square, Grid::HexPointy, Grid::HexFlat].into_iter().for_each(|grid|
+        {
+            (1..20).for_each(|cells|
+            {
+                let total = grid.size(cells, cells, size);
+
+                assert_eq!(grid.columns(total.x, size), cells, "{grid}");
+                assert_eq!(grid.rows(total.y, size), cells, "{grid}");
+            });
+        });
+
+        // a bit more than half a row still counts
+        assert_eq!(Grid::Square.rows(96, size), 10);
+        assert_eq!(Grid::Square.rows(94, size), 9);
+    }
+
+    #[test]
+    fn hexes_cover_inside()
+    {
+        [Grid::HexPointy, Grid::HexFlat].into_iter().for_each(|grid|
+        {
+            [(8, 8), (13, 13), (32, 32), (24, 16), (9, 14)].into_iter().for_each(|(x, y)|
+            {
+                let size = Vec2{x, y};
+
+                let (columns, rows) = (5, 6);
+
+                let total = grid.size(columns, rows, size);
+                let mask = grid.mask(size).unwrap();
+
+                let mut covered = vec![false; (total.x * total.y) as usize];
+
+                (0..rows).flat_map(|y| (0..columns).map(move |x| Vec2{x, y})).for_each(|cell|
+                {
+                    let position = grid.position(cell, size);
+
+                    assert!(position.x + size.x <= total.x && position.y + size.y <= total.y);
+
+                    mask.iter().enumerate().filter(|(_, inside)| **inside).for_each(|(i, _)|
+                    {
+                        let x = position.x + i as u32 % size.x;
+                        let y = position.y + i as u32 / size.x;
+
+                        covered[(y * total.x + x) as usize] = true;
+                    });
+                });
+
+                // the zigzag edges are always uncovered, but away from them everything is
+                for y in size.y..(total.y - size.y)
+                {
+                    for x in size.x..(total.x - size.x)
+                    {
+                        assert!(covered[(y * total.x + x) as usize], "{grid} {size:?} ({x}, {y})");
+                    }
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs,
+    io,
+    path::Path
+};
+
+use crate::manifest::{self, Manifest};
+
+
+// a page with the collage where every tile links to its original file and
+// tells its name and match error when hovered
+pub fn save(path: &Path, image: &Path, manifest: &Manifest) -> io::Result<()>
+{
+    fs::write(path, page(path, image, manifest))
+}
+
+fn page(path: &Path, image: &Path, manifest: &Manifest) -> String
+{
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let (width, height) = (manifest.width.max(1) as f64, manifest.height.max(1) as f64);
+
+    // percentages so the links stay on their tiles when the image gets scaled down
+    let percent = |value: u32, total: f64| format!("{:.4}%", value as f64 / total * 100.0);
+
+    let links = manifest.cells.iter().map(|cell|
+    {
+        let name = |path: &Path|
+        {
+            path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+        };
+
+        let mut title = name(&cell.source);
+
+        if !cell.overlays.is_empty()
+        {
+            let overlays = cell.overlays.iter().map(|layer| name(&layer.path))
+                .collect::<Vec<_>>();
+
+            title += &format!("\nwith {}", overlays.join(", "));
+        }
+
+        title += &format!("\nerror {:.2}", cell.error);
+
+        format!(
+            "<a href=\"{}\" title=\"{}\" style=\"left: {}; top: {}; width: {}; height: {}\"></a>\n",
+            manifest::escape(&manifest::link(&cell.source, directory)),
+            manifest::escape(&title),
+            percent(cell.x, width),
+            percent(cell.y, height),
+            percent(cell.width, width),
+            percent(cell.height, height)
+        )
+    }).collect::<String>();
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>collage</title>
+<style>
+.collage {{ position: relative; display: inline-block; max-width: 100%; }}
+.collage img {{ display: block; max-width: 100%; height: auto; }}
+.collage a {{ position: absolute; }}
+.collage a:hover {{ outline: 2px solid white; z-index: 1; }}
+</style>
+</head>
+<body>
+<div class=\"collage\">
+<img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"collage\">
+{links}</div>
+</body>
+</html>
+",
+        manifest::escape(&manifest::link(image, directory)),
+        manifest.width,
+        manifest.height
+    )
+}
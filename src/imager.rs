@@ -1,8 +1,6 @@
 use std::{
     fs,
     io,
-    thread,
-    time::Duration,
     sync::Arc,
     borrow::Borrow,
     path::{Path, PathBuf}
@@ -23,30 +21,229 @@ use image::{
     error::ImageError
 };
 
-use crate::{Vec2, Lab};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    Vec2,
+    Lab,
+    colors::ColorSpace,
+    pool::WorkerPool,
+    cache::{TileCache, CacheKey, CacheEntry, FileStamp}
+};
 
 
 type LabInner = Rgb32FImage;
 
+// finer levels than this arent worth it over just comparing the pixels
+const PYRAMID_MAX_BLOCKS: u32 = 16;
+
+#[derive(Debug, Clone)]
+pub struct PyramidLevel
+{
+    pub blocks: Vec2,
+    // block averages scaled by the square root of the pixels in the block, so the
+    // squared distance between two levels is the error of the averaged images
+    pub values: Vec<f32>,
+    pub weights: Vec<f32>
+}
+
+impl PyramidLevel
+{
+    fn new(pixels: impl Iterator<Item=Lab>, size: Vec2, blocks: Vec2) -> Self
+    {
+        let blocks_amount = (blocks.x * blocks.y) as usize;
+
+        let mut sums = vec![Lab{l: 0.0, a: 0.0, b: 0.0}; blocks_amount];
+        let mut counts = vec![0_u32; blocks_amount];
+
+        pixels.enumerate().for_each(|(index, pixel)|
+        {
+            let x = index as u32 % size.x;
+            let y = index as u32 / size.x;
+
+            let block = ((y * blocks.y / size.y) * blocks.x + (x * blocks.x / size.x)) as usize;
+
+            let sum = &mut sums[block];
+            sum.l += pixel.l;
+            sum.a += pixel.a;
+            sum.b += pixel.b;
+
+            counts[block] += 1;
+        });
+
+        let weights = counts.iter().map(|count| (*count as f32).sqrt()).collect::<Vec<_>>();
+
+        let values = sums.into_iter().zip(counts).flat_map(|(sum, count)|
+        {
+            let scale = (count as f32).sqrt() / count.max(1) as f32;
+
+            [sum.l * scale, sum.a * scale, sum.b * scale]
+        }).collect();
+
+        Self{blocks, values, weights}
+    }
+
+    // a lower bound on the summed cie76 error between the two full images, squared
+    // per pixel if squared is set (each block contributes at least its amount of
+    // pixels times the error between the averages)
+    pub fn bound(&self, other: &Self, squared: bool) -> f32
+    {
+        let blocks = self.values.chunks_exact(3).zip(other.values.chunks_exact(3));
+
+        let differences = blocks.map(|(a, b)|
+        {
+            a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum::<f32>()
+        });
+
+        if squared
+        {
+            differences.sum()
+        } else
+        {
+            differences.zip(&self.weights).map(|(difference, weight)|
+            {
+                difference.sqrt() * weight
+            }).sum()
+        }
+    }
+}
+
+// the image averaged down to 1x1, 2x2, 4x4 and so on
+#[derive(Debug, Clone)]
+pub struct Pyramid(Vec<PyramidLevel>);
+
+impl Pyramid
+{
+    pub fn new(pixels: impl Iterator<Item=Lab> + Clone, size: Vec2) -> Self
+    {
+        // every block covers at least two pixels, otherwise its just the image again
+        let largest = (size.x.max(size.y) / 2).clamp(1, PYRAMID_MAX_BLOCKS);
+
+        let levels = (0..).map(|level| 1_u32 << level)
+            .take_while(|blocks| *blocks <= largest)
+            .map(|blocks|
+            {
+                let blocks = Vec2{x: blocks.min(size.x), y: blocks.min(size.y)};
+
+                PyramidLevel::new(pixels.clone(), size, blocks)
+            }).collect();
+
+        Self(levels)
+    }
+
+    pub fn levels(&self) -> &[PyramidLevel]
+    {
+        &self.0
+    }
+
+    // the finest level with at most this many blocks on each side
+    pub fn level_with(&self, blocks: u32) -> Option<&PyramidLevel>
+    {
+        self.0.iter().take_while(|level|
+        {
+            level.blocks.x <= blocks && level.blocks.y <= blocks
+        }).last()
+    }
+}
+
 // i hate this library
 #[derive(Debug, Clone)]
-pub struct LabImage(LabInner);
+pub struct LabImage
+{
+    image: LabInner,
+    space: ColorSpace,
+    pyramid: Pyramid
+}
 
 impl LabImage
 {
+    pub fn from_rgb(image: RgbImage, space: ColorSpace) -> Self
+    {
+        Self::from_rgb_f32(image.convert(), space)
+    }
+
+    pub fn from_rgb_f32(mut image: Rgb32FImage, space: ColorSpace) -> Self
+    {
+        image.pixels_mut().for_each(|pixel|
+        {
+            let lab = space.from_rgb(*pixel);
+
+            *pixel = Rgb::from([lab.l, lab.a, lab.b]);
+        });
+
+        Self::from_lab(image, space)
+    }
+
+    // values are already in the color space, l a b for every pixel
+    pub fn from_lab_values(
+        width: u32,
+        height: u32,
+        values: Vec<f32>,
+        space: ColorSpace
+    ) -> Option<Self>
+    {
+        LabInner::from_raw(width, height, values).map(|image| Self::from_lab(image, space))
+    }
+
+    fn from_lab(image: LabInner, space: ColorSpace) -> Self
+    {
+        let pyramid = Pyramid::new(
+            image.pixels().map(|Rgb([l, a, b])| Lab{l: *l, a: *a, b: *b}),
+            Vec2{x: image.width(), y: image.height()}
+        );
+
+        Self{image, space, pyramid}
+    }
+
+    pub fn to_rgb_f32(&self) -> Rgb32FImage
+    {
+        let mut image = self.image.clone();
+
+        image.pixels_mut().for_each(|pixel|
+        {
+            let Rgb([l, a, b]) = *pixel;
+
+            let rgb = self.space.to_rgb(Lab{l, a, b});
+
+            *pixel = rgb.map(|value| value.clamp(0.0, 1.0));
+        });
+
+        image
+    }
+
+    pub fn to_rgb(&self) -> RgbImage
+    {
+        self.to_rgb_f32().convert()
+    }
+
     pub fn width(&self) -> u32
     {
-        self.0.width()
+        self.image.width()
     }
 
     pub fn height(&self) -> u32
     {
-        self.0.height()
+        self.image.height()
+    }
+
+    pub fn space(&self) -> ColorSpace
+    {
+        self.space
+    }
+
+    pub fn lab_values(&self) -> &[f32]
+    {
+        self.image.as_raw()
     }
 
-    pub fn pixels(&self) -> impl Iterator<Item=Lab> + '_
+    pub fn pyramid(&self) -> &Pyramid
     {
-        self.0.pixels()
+        &self.pyramid
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item=Lab> + Clone + '_
+    {
+        self.image.pixels()
             .copied()
             .map(|Rgb([l, a, b])| Lab{l, a, b})
     }
@@ -57,7 +254,7 @@ impl LabImage
         size: Vec2
     ) -> Vec<Lab>
     {
-        self.0.view(position.x, position.y, size.x, size.y)
+        self.image.view(position.x, position.y, size.x, size.y)
             .pixels()
             .map(|(_x, _y, pixel)| pixel)
             .map(|Rgb([l, a, b])| Lab{l, a, b})
@@ -65,29 +262,6 @@ impl LabImage
     }
 }
 
-impl From<RgbImage> for LabImage
-{
-    fn from(value: RgbImage) -> Self
-    {
-        <Self as From<Rgb32FImage>>::from(value.convert())
-    }
-}
-
-impl From<Rgb32FImage> for LabImage
-{
-    fn from(mut value: Rgb32FImage) -> Self
-    {
-        value.pixels_mut().for_each(|pixel|
-        {
-            let lab = Lab::from(*pixel);
-
-            *pixel = Rgb::from([lab.l, lab.a, lab.b]);
-        });
-
-        Self(value)
-    }
-}
-
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Error
@@ -105,11 +279,6 @@ impl Error
             error
         }
     }
-
-    pub fn error(&self) -> &ImageError
-    {
-        &self.error
-    }
 }
 
 impl From<io::Error> for Error
@@ -122,17 +291,134 @@ impl From<io::Error> for Error
 
 pub struct Config
 {
-    pub image_size: u32,
+    pub image_size: Vec2,
     pub allow_rotate: bool,
     pub allow_invert: bool,
-    pub depth: u32
+    pub depth: u32,
+    pub space: ColorSpace,
+    pub cache: Option<PathBuf>,
+    pub pool: WorkerPool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Transform
+{
+    // clockwise quarter turns
+    pub rotation: u8,
+    // mirrored horizontally before rotating
+    pub mirrored: bool,
+    pub inverted: bool
+}
+
+impl Transform
+{
+    // every allowed transform, in the order the library gets them in, tiles that
+    // arent square only get half turns so they keep their shape
+    pub fn variants(allow_rotate: bool, allow_invert: bool, square: bool) -> Vec<Self>
+    {
+        let mut transforms = vec![Self::default()];
+
+        if allow_rotate
+        {
+            let rotations = (1..4).filter(|rotation| square || rotation % 2 == 0);
+
+            transforms.extend(rotations.flat_map(|rotation|
+            {
+                [false, true].map(|mirrored| Self{rotation, mirrored, inverted: false})
+            }));
+        }
+
+        if allow_invert
+        {
+            let inverted = transforms.iter().map(|transform|
+            {
+                Self{inverted: true, ..*transform}
+            }).collect::<Vec<_>>();
+
+            transforms.extend(inverted);
+        }
+
+        transforms
+    }
+
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage
+    {
+        let image = if self.mirrored { image.fliph() } else { image.clone() };
+
+        let mut image = match self.rotation % 4
+        {
+            1 => image.rotate90(),
+            2 => image.rotate180(),
+            3 => image.rotate270(),
+            _ => image
+        };
+
+        if self.inverted
+        {
+            image.invert();
+        }
+
+        image
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layer
+{
+    pub source: usize,
+    pub transform: Transform
+}
+
+// everything needed to make a tile again from the original files
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TileSource
+{
+    pub path: PathBuf,
+    pub transform: Transform,
+    // transparent images blended on top, bottom first
+    pub overlays: Vec<(PathBuf, Transform)>
+}
+
+impl TileSource
+{
+    pub fn load(&self, size: Vec2) -> Result<RgbImage, Error>
+    {
+        let open = |path: &Path, transform: &Transform|
+        {
+            image::open(path).map(|image|
+            {
+                transform.apply(&Imager::resize_image(image, size))
+            }).map_err(|err| Error::new(path, err))
+        };
+
+        let image = open(&self.path, &self.transform)?;
+
+        if self.overlays.is_empty()
+        {
+            return Ok(image.into_rgb8());
+        }
+
+        let overlay = self.overlays.iter().map(|(path, transform)|
+        {
+            open(path, transform).map(|image| image.into_rgba32f())
+        }).reduce(|back, other|
+        {
+            Ok(Imager::combine_images_f32(back?, other?))
+        }).expect("overlays cant be empty")?;
+
+        Ok(DynamicImage::from(Imager::combine_images(image.into_rgba8(), overlay)).into_rgb8())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ImagePair<I=RgbImage>
 {
     pub image: I,
-    pub name: String
+    pub name: String,
+    // index of the file this image was made from
+    pub source: usize,
+    pub transform: Transform,
+    pub overlays: Vec<Layer>
 }
 
 impl<T> ImagePair<T>
@@ -141,7 +427,10 @@ impl<T> ImagePair<T>
     {
         ImagePair{
             image: f(self.image),
-            name: self.name
+            name: self.name,
+            source: self.source,
+            transform: self.transform,
+            overlays: self.overlays
         }
     }
 
@@ -149,7 +438,10 @@ impl<T> ImagePair<T>
     {
         ImagePair{
             image: f(&self.image),
-            name: self.name.clone()
+            name: self.name.clone(),
+            source: self.source,
+            transform: self.transform,
+            overlays: self.overlays.clone()
         }
     }
 }
@@ -159,16 +451,35 @@ pub type LabImagesContainer = Vec<LabImage>;
 
 pub struct Imager
 {
-    images: Arc<ImagesContainer>
+    images: Arc<ImagesContainer>,
+    lab_images: Arc<LabImagesContainer>,
+    paths: Vec<PathBuf>
 }
 
 impl Imager
 {
     pub fn new<P: AsRef<Path>>(directory: P, config: Config) -> Result<Self, Error>
     {
-        let images = Arc::from(Self::create_images(directory.as_ref(), config)?);
+        let directory = directory.as_ref();
+        let paths = Self::image_paths(directory)?;
 
-        Ok(Self{images})
+        let (images, lab_images) = Self::create_images(directory, &paths, config)?;
+
+        Ok(Self{images: Arc::new(images), lab_images: Arc::new(lab_images), paths})
+    }
+
+    pub fn tile_source(&self, index: usize) -> TileSource
+    {
+        let image = &self.images[index];
+
+        TileSource{
+            path: self.paths[image.source].clone(),
+            transform: image.transform,
+            overlays: image.overlays.iter().map(|layer|
+            {
+                (self.paths[layer.source].clone(), layer.transform)
+            }).collect()
+        }
     }
 
     pub fn images(&self) -> Arc<ImagesContainer>
@@ -176,32 +487,150 @@ impl Imager
         self.images.clone()
     }
 
-    fn create_images(directory: &Path, config: Config) -> Result<ImagesContainer, Error>
+    pub fn lab_images(&self) -> Arc<LabImagesContainer>
+    {
+        self.lab_images.clone()
+    }
+
+    fn create_images(
+        directory: &Path,
+        paths: &[PathBuf],
+        config: Config
+    ) -> Result<(ImagesContainer, LabImagesContainer), Error>
     {
         if config.depth == 0
         {
-            Self::created_unpermuted_images(directory, config)
+            Self::created_unpermuted_images(directory, paths, config)
         } else
         {
-            Self::created_permuted_images(directory, config)
+            let (space, pool) = (config.space, config.pool);
+
+            let images = Self::created_permuted_images(paths, config)?;
+
+            let lab_images = pool.map(images.iter(), |pair|
+            {
+                LabImage::from_rgb(pair.image.clone(), space)
+            });
+
+            Ok((images, lab_images))
         }
     }
 
     fn created_unpermuted_images(
         directory: &Path,
+        image_paths: &[PathBuf],
         config: Config
-    ) -> Result<ImagesContainer, Error>
+    ) -> Result<(ImagesContainer, LabImagesContainer), Error>
     {
-        Self::create_mapped_images(directory, config, |image| image.into_rgb8())
+        let mut cache = config.cache.clone().map(TileCache::load);
+
+        let key = |path: &Path|
+        {
+            CacheKey{
+                path: path.to_owned(),
+                tile_width: config.image_size.x,
+                tile_height: config.image_size.y,
+                allow_rotate: config.allow_rotate,
+                allow_invert: config.allow_invert,
+                space: config.space
+            }
+        };
+
+        let files = config.pool.map(image_paths.iter().enumerate(), |(source, image_path)|
+        {
+            let name = image_path.file_stem()
+                .expect("image path must be a valid image")
+                .to_string_lossy().into_owned();
+
+            let stamp = FileStamp::new(image_path)?;
+
+            let cached = cache.as_ref().and_then(|cache| cache.get(&key(image_path), stamp));
+
+            let tiles = if let Some(entry) = cached
+            {
+                entry.tiles.clone()
+            } else
+            {
+                let image = image::open(image_path).map_err(|err|
+                {
+                    Error::new(image_path, err)
+                })?;
+
+                let image = ImagePair{
+                    image: Self::resize_image(image, config.image_size),
+                    name: name.clone(),
+                    source,
+                    transform: Transform::default(),
+                    overlays: Vec::new()
+                };
+
+                Self::image_variants(image, &config).into_iter().map(|pair|
+                {
+                    let image = pair.image.into_rgb8();
+                    let lab = LabImage::from_rgb(image.clone(), config.space);
+
+                    (image, lab)
+                }).collect()
+            };
+
+            let entry = CacheEntry{stamp, tiles};
+
+            Ok::<_, Error>((name, entry, cached.is_none()))
+        }).into_iter().collect::<Result<Vec<_>, Error>>()?;
+
+        let mut images = Vec::new();
+        let mut lab_images = Vec::new();
+
+        let files = image_paths.iter().zip(files).enumerate();
+
+        let transforms = Transform::variants(
+            config.allow_rotate,
+            config.allow_invert,
+            config.image_size.x == config.image_size.y
+        );
+
+        for (source, (image_path, (name, entry, changed))) in files
+        {
+            entry.tiles.iter().cloned().zip(transforms.iter()).for_each(|((image, lab), transform)|
+            {
+                images.push(ImagePair{
+                    image,
+                    name: name.clone(),
+                    source,
+                    transform: *transform,
+                    overlays: Vec::new()
+                });
+
+                lab_images.push(lab);
+            });
+
+            if let Some(cache) = cache.as_mut().filter(|_| changed)
+            {
+                cache.insert(key(image_path), entry);
+            }
+        }
+
+        if let Some(mut cache) = cache
+        {
+            cache.retain_directory(directory, image_paths);
+
+            // the collage doesnt need the cache, next run just decodes everything again
+            if let Err(err) = cache.save()
+            {
+                eprintln!("warning: couldnt save the tile cache: {err}");
+            }
+        }
+
+        Ok((images, lab_images))
     }
 
     fn created_permuted_images(
-        directory: &Path,
+        paths: &[PathBuf],
         config: Config
     ) -> Result<ImagesContainer, Error>
     {
         let depth = config.depth;
-        let images = Self::create_mapped_images(directory, config, |image| image.into_rgba8())?;
+        let images = Self::create_mapped_images(paths, config, |image| image.into_rgba8())?;
 
         let (transparent_images, solid_images): (Vec<_>, Vec<_>) =
             images.into_iter().partition(|image|
@@ -216,7 +645,7 @@ impl Imager
                 contains_transparency
             });
 
-        let transparent_images = Self::recombine_transparents(
+        let combined_transparents = Self::recombine_transparents(
             transparent_images.iter().map(|img| &img.image),
             depth
         );
@@ -225,51 +654,54 @@ impl Imager
 
         for solid_image in solid_images.iter()
         {
-            for transparent_image in transparent_images.iter()
+            for (transparent_image, components) in combined_transparents.iter()
             {
                 let permutation = Self::combine_images(
                     solid_image.image.clone(),
                     transparent_image
                 );
 
+                let overlays = components.iter().map(|index|
+                {
+                    let transparent = &transparent_images[*index];
+
+                    Layer{source: transparent.source, transform: transparent.transform}
+                }).collect();
+
                 let permutation = ImagePair{
                     image: permutation,
-                    name: String::new()
+                    name: String::new(),
+                    source: solid_image.source,
+                    transform: solid_image.transform,
+                    overlays
                 };
 
                 permuted_images.push(permutation);
             }
         }
 
-        permuted_images.extend(solid_images.into_iter());
+        permuted_images.extend(solid_images);
 
         let images = permuted_images.into_iter().map(|image|
         {
-            let ImagePair{
-                image,
-                name
-            } = image;
-
-            ImagePair{
-                image: image.convert(),
-                name
-            }
+            image.map_image(|image| image.convert())
         }).collect::<Vec<_>>();
 
         Ok(images)
     }
 
+    // every combination along with which original images its made of, bottom first
     fn recombine_transparents<I>(
         original_transparent_images: impl Iterator<Item=I>,
         depth: u32
-    ) -> Vec<Rgba32FImage>
+    ) -> Vec<(Rgba32FImage, Vec<usize>)>
     where
         I: Borrow<RgbaImage>
     {
         // pre convert to f32 for faster combining
-        let original_transparent_images = original_transparent_images.map(|image|
+        let original_transparent_images = original_transparent_images.enumerate().map(|(index, image)|
         {
-            image.borrow().convert()
+            (image.borrow().convert(), vec![index])
         }).collect::<Vec<_>>();
 
         if depth > 1
@@ -282,16 +714,20 @@ impl Imager
             {
                 let mut this_transparents = Vec::new();
 
-                for transparent_image in previous_transparent_images.clone()
+                for (transparent_image, components) in previous_transparent_images.clone()
                 {
-                    for original_transparent in original_transparent_images.iter()
+                    for (original_transparent, original_components) in original_transparent_images.iter()
                     {
                         let combined = Self::combine_images_f32(
                             transparent_image.clone(),
                             original_transparent
                         );
 
-                        this_transparents.push(combined);
+                        let components = components.iter().chain(original_components)
+                            .copied()
+                            .collect();
+
+                        this_transparents.push((combined, components));
                     }
                 }
 
@@ -360,14 +796,14 @@ impl Imager
     }
 
     fn create_mapped_images<T, F>(
-        directory: &Path,
+        paths: &[PathBuf],
         config: Config,
         mut f: F
     ) -> Result<Vec<ImagePair<T>>, Error>
     where
         F: FnMut(DynamicImage) -> T
     {
-        Self::create_dynamic_images(directory, config).map(|images|
+        Self::create_dynamic_images(paths, config).map(|images|
         {
             images.into_iter().map(|img|
             {
@@ -377,143 +813,80 @@ impl Imager
     }
 
     fn create_dynamic_images(
-        directory: &Path,
+        paths: &[PathBuf],
         config: Config
     ) -> Result<Vec<ImagePair<DynamicImage>>, Error>
     {
-        let mut images = Self::folder_images(directory, config.image_size)?;
-
-        if config.allow_rotate
-        {
-            let mut rotated = {
-                let images_original = images.iter();
-                let images_mirrored = images_original.clone().map(|image|
-                {
-                    image.map_image_ref(|image| image.fliph())
-                }).collect::<Vec<_>>();
-
-                let images = images_original.chain(images_mirrored.iter());
+        let images = Self::folder_images(paths, config.image_size, config.pool)?;
 
-                let rotated90 = images.clone().map(|image|
-                {
-                    image.map_image_ref(|image| image.rotate90())
-                });
-
-                let rotated180 = images.clone().map(|image|
-                {
-                    image.map_image_ref(|image| image.rotate180())
-                });
-
-                let rotated270 = images.map(|image|
-                {
-                    image.map_image_ref(|image| image.rotate270())
-                });
-
-                rotated90.chain(rotated180).chain(rotated270).collect::<Vec<_>>()
-            };
+        Ok(images.into_iter().flat_map(|image| Self::image_variants(image, &config)).collect())
+    }
 
-            images.append(&mut rotated);
-        }
+    // the image and every rotated, mirrored and inverted version of it thats allowed
+    fn image_variants(
+        image: ImagePair<DynamicImage>,
+        config: &Config
+    ) -> Vec<ImagePair<DynamicImage>>
+    {
+        let square = config.image_size.x == config.image_size.y;
 
-        if config.allow_invert
+        let transforms = Transform::variants(config.allow_rotate, config.allow_invert, square);
+        transforms.into_iter().map(|transform|
         {
-            let mut inverted = images.iter().cloned().map(|mut image|
-            {
-                image.image.invert();
-
-                image
-            }).collect::<Vec<_>>();
-
-            images.append(&mut inverted);
-        }
-
-        Ok(images)
+            ImagePair{transform, ..image.map_image_ref(|image| transform.apply(image))}
+        }).collect()
     }
 
-    fn folder_images(
-        directory: &Path,
-        image_size: u32
-    ) -> Result<Vec<ImagePair<DynamicImage>>, Error>
+    fn image_paths(directory: &Path) -> Result<Vec<PathBuf>, Error>
     {
-        let image_handles = directory.read_dir()?.filter(|image_file|
+        directory.read_dir()?.filter(|image_file|
         {
             image_file.as_ref().map(|image_file|
             {
-                let is_file = image_file.file_type().ok().map(|file_type| file_type.is_file())
-                .unwrap_or(false);
-
-                is_file
+                image_file.file_type().ok().map(|file_type| file_type.is_file())
+                    .unwrap_or(false)
             }).unwrap_or(true)
         }).map(|image_file| -> Result<_, Error>
         {
             Ok(image_file?.path())
-        }).map(|image_path|
+        }).collect()
+    }
+
+    fn folder_images(
+        image_paths: &[PathBuf],
+        image_size: Vec2,
+        pool: WorkerPool
+    ) -> Result<Vec<ImagePair<DynamicImage>>, Error>
+    {
+        pool.map(image_paths.iter().enumerate(), |(source, image_path)|
         {
-            thread::spawn(move || -> Result<ImagePair<DynamicImage>, _>
+            let image = image::open(image_path).map_err(|err|
             {
-                let image_path = image_path?;
+                Error::new(image_path, err)
+            })?;
 
-                let image = loop
-                {
-                    let image = image::open(&image_path).map_err(|err|
-                    {
-                        Error::new(image_path.clone(), err)
-                    });
+            let image = Self::resize_image(image, image_size);
+            let name = image_path.file_stem()
+                .expect("image path must be a valid image")
+                .to_string_lossy().into_owned();
 
-                    let is_recoverable = |err: &Error|
-                    {
-                        let image_error = err.error();
-
-                        match image_error
-                        {
-                            ImageError::IoError(io_error) =>
-                            {
-                                io_error.raw_os_error().map(|code| code == 24).unwrap_or(false)
-                            },
-                            _ => false
-                        }
-                    };
-
-                    let image = match image
-                    {
-                        Ok(x) => x,
-                        Err(err) if is_recoverable(&err) =>
-                        {
-                            thread::sleep(Duration::from_millis(50));
-                            continue;
-                        },
-                        Err(x) => return Err(x)
-                    };
-
-                    break Ok::<_, Error>(image);
-                }?;
-
-                let image = Self::resize_image(image, image_size);
-                let name = image_path.file_stem()
-                    .expect("image path must be a valid image")
-                    .to_string_lossy().into_owned();
-                
-                let pair = ImagePair{image, name};
-
-                Ok(pair)
-            })
-        }).collect::<Vec<_>>();
-
-        let images = image_handles.into_iter().map(|handle|
-        {
-            handle.join().unwrap()
-        }).collect::<Result<Vec<_>, Error>>()?;
+            let pair = ImagePair{
+                image,
+                name,
+                source,
+                transform: Transform::default(),
+                overlays: Vec::new()
+            };
 
-        Ok(images)
+            Ok(pair)
+        }).into_iter().collect::<Result<_, Error>>()
     }
 
-    fn resize_image(image: DynamicImage, image_size: u32) -> DynamicImage
+    pub fn resize_image(image: DynamicImage, image_size: Vec2) -> DynamicImage
     {
         let filter_type = FilterType::CatmullRom;
 
-        let resized = image.resize_to_fill(image_size, image_size, filter_type);
-
-        resized
+        image.resize_to_fill(image_size.x, image_size.y, filter_type)
     }
 
     pub fn save<P: AsRef<Path>>(&self, output_directory: P)
@@ -534,3 +907,116 @@ impl Imager
         })
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn lab_image_round_trip()
+    {
+        let image = RgbImage::from_fn(8, 5, |x, y|
+        {
+            Rgb::from([(x * 30) as u8, (y * 50) as u8, ((x + y) * 20) as u8])
+        });
+
+        [ColorSpace::Cielab, ColorSpace::Oklab].into_iter().for_each(|space|
+        {
+            let lab = LabImage::from_rgb(image.clone(), space);
+
+            assert_eq!(lab.space(), space);
+            assert_eq!(lab.to_rgb(), image);
+        });
+    }
+
+    #[test]
+    fn pyramid_is_lower_bound()
+    {
+        let images = (0..20_u32).map(|i|
+        {
+            let image = RgbImage::from_fn(7, 9, |x, y|
+            {
+                Rgb::from([(i * 11) as u8, (x * 30 + i * 3) as u8, ((y * 27 + i * 9) % 256) as u8])
+            });
+
+            LabImage::from_rgb(image, ColorSpace::Cielab)
+        }).collect::<Vec<_>>();
+
+        let big = LabImage::from_rgb(RgbImage::new(64, 64), ColorSpace::Cielab);
+        let finest = big.pyramid().levels().iter().map(|level| level.blocks).max();
+
+        assert_eq!(finest, Some(Vec2{x: PYRAMID_MAX_BLOCKS, y: PYRAMID_MAX_BLOCKS}));
+
+        images.iter().for_each(|a|
+        {
+            assert_eq!(a.pyramid().levels().len(), 3);
+
+            images.iter().for_each(|b|
+            {
+                let squared_error: f32 = a.pixels().zip(b.pixels()).map(|(a, b)|
+                {
+                    a.distance(b)
+                }).sum();
+
+                let error: f32 = a.pixels().zip(b.pixels()).map(|(a, b)|
+                {
+                    a.distance(b).sqrt()
+                }).sum();
+
+                let levels = a.pyramid().levels().iter().zip(b.pyramid().levels());
+
+                let mut previous = 0.0;
+                levels.for_each(|(a_level, b_level)|
+                {
+                    let bound = a_level.bound(b_level, true);
+
+                    assert!(bound <= squared_error * 1.0001 + 0.001);
+                    assert!(a_level.bound(b_level, false) <= error * 1.0001 + 0.001);
+
+                    // finer levels can only get closer to the real error
+                    assert!(bound * 1.0001 + 0.001 >= previous);
+                    previous = bound;
+                });
+            });
+        });
+    }
+
+    #[test]
+    fn non_square_tiles()
+    {
+        let square = Transform::variants(true, true, true);
+        let wide = Transform::variants(true, true, false);
+
+        assert_eq!(square.len(), 14);
+        assert_eq!(wide.len(), 6);
+
+        // quarter turns would swap the width and the height
+        assert!(wide.iter().all(|transform| transform.rotation % 2 == 0));
+        assert!(wide.iter().all(|transform| square.contains(transform)));
+
+        // red and blue sides with a green middle, a wide image going into a tall tile
+        let image = RgbImage::from_fn(40, 10, |x, _|
+        {
+            match x
+            {
+                0..=14 => Rgb([255, 0, 0]),
+                15..=24 => Rgb([0, 255, 0]),
+                _ => Rgb([0, 0, 255])
+            }
+        });
+
+        let size = Vec2{x: 4, y: 8};
+        let resized = Imager::resize_image(DynamicImage::ImageRgb8(image), size).into_rgb8();
+
+        assert_eq!(resized.dimensions(), (4, 8));
+
+        // scaled evenly and cropped, so only the middle is left instead of all three squashed
+        assert!(resized.pixels().all(|Rgb([r, g, b])| *g > 200 && *r < 50 && *b < 50));
+
+        let turned = Transform{rotation: 2, mirrored: true, inverted: false}
+            .apply(&DynamicImage::ImageRgb8(resized));
+
+        assert_eq!(turned.dimensions(), (4, 8));
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap
+};
+
+use crate::{
+    LabImage,
+    imager::Pyramid
+};
+
+
+// each tile gets described by a grid of this many by this many averaged blocks
+pub const DESCRIPTOR_BLOCKS: u32 = 4;
+
+const LEAF_SIZE: usize = 8;
+
+// the squared distance between two descriptors never goes above the
+// squared cie76 error between the full images
+pub fn descriptor(pyramid: &Pyramid) -> Vec<f32>
+{
+    pyramid.level_with(DESCRIPTOR_BLOCKS).map(|level| level.values.clone()).unwrap_or_default()
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32
+{
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+#[derive(Debug)]
+enum NodeKind
+{
+    Leaf(Vec<usize>),
+    Split{left: usize, right: usize}
+}
+
+#[derive(Debug)]
+struct Node
+{
+    kind: NodeKind,
+    lower: Vec<f32>,
+    upper: Vec<f32>
+}
+
+impl Node
+{
+    fn distance(&self, point: &[f32]) -> f32
+    {
+        point.iter().zip(self.lower.iter().zip(self.upper.iter())).map(|(value, (lower, upper))|
+        {
+            if value < lower
+            {
+                (lower - value).powi(2)
+            } else if value > upper
+            {
+                (value - upper).powi(2)
+            } else
+            {
+                0.0
+            }
+        }).sum()
+    }
+}
+
+// a k-d tree over the tile descriptors
+pub struct TileIndex
+{
+    dimensions: usize,
+    descriptors: Vec<f32>,
+    nodes: Vec<Node>
+}
+
+impl TileIndex
+{
+    pub fn new<'a>(images: impl Iterator<Item=&'a LabImage>) -> Self
+    {
+        let descriptors = images.map(|image|
+        {
+            descriptor(image.pyramid())
+        }).collect::<Vec<_>>();
+
+        let dimensions = descriptors.first().map(|x| x.len()).unwrap_or(0);
+
+        let mut this = Self{
+            dimensions,
+            descriptors: descriptors.into_iter().flatten().collect(),
+            nodes: Vec::new()
+        };
+
+        let amount = this.descriptors.len().checked_div(dimensions).unwrap_or(0);
+
+        if amount != 0
+        {
+            this.build_node((0..amount).collect());
+        }
+
+        this
+    }
+
+    fn point(&self, index: usize) -> &[f32]
+    {
+        let start = index * self.dimensions;
+
+        &self.descriptors[start..start + self.dimensions]
+    }
+
+    fn build_node(&mut self, mut indices: Vec<usize>) -> usize
+    {
+        let mut lower = vec![f32::INFINITY; self.dimensions];
+        let mut upper = vec![f32::NEG_INFINITY; self.dimensions];
+
+        indices.iter().for_each(|index|
+        {
+            self.point(*index).iter().enumerate().for_each(|(dimension, value)|
+            {
+                lower[dimension] = lower[dimension].min(*value);
+                upper[dimension] = upper[dimension].max(*value);
+            });
+        });
+
+        let node_index = self.nodes.len();
+
+        if indices.len() <= LEAF_SIZE
+        {
+            self.nodes.push(Node{kind: NodeKind::Leaf(indices), lower, upper});
+
+            return node_index;
+        }
+
+        // split along the widest dimension
+        let split_dimension = (0..self.dimensions).max_by(|a, b|
+        {
+            let a = upper[*a] - lower[*a];
+            let b = upper[*b] - lower[*b];
+
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        }).unwrap_or(0);
+
+        indices.sort_unstable_by(|a, b|
+        {
+            let a = self.point(*a)[split_dimension];
+            let b = self.point(*b)[split_dimension];
+
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        });
+
+        let right_indices = indices.split_off(indices.len() / 2);
+
+        // placeholder until the children are built
+        self.nodes.push(Node{kind: NodeKind::Leaf(Vec::new()), lower, upper});
+
+        let left = self.build_node(indices);
+        let right = self.build_node(right_indices);
+
+        self.nodes[node_index].kind = NodeKind::Split{left, right};
+
+        node_index
+    }
+
+    // every tile ordered by how close its descriptor is to the query descriptor
+    pub fn nearest(&self, query: Vec<f32>) -> Nearest<'_>
+    {
+        let mut queue = BinaryHeap::new();
+
+        if !self.nodes.is_empty()
+        {
+            queue.push(Reverse(QueueEntry{
+                distance: self.nodes[0].distance(&query),
+                item: QueueItem::Node(0)
+            }));
+        }
+
+        Nearest{index: self, query, queue}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueueItem
+{
+    Node(usize),
+    Tile(usize)
+}
+
+#[derive(Debug, PartialEq)]
+struct QueueEntry
+{
+    distance: f32,
+    item: QueueItem
+}
+
+impl Eq for QueueEntry {}
+
+impl PartialOrd for QueueEntry
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueEntry
+{
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+pub struct Nearest<'a>
+{
+    index: &'a TileIndex,
+    query: Vec<f32>,
+    queue: BinaryHeap<Reverse<QueueEntry>>
+}
+
+impl Iterator for Nearest<'_>
+{
+    // tile index and the squared descriptor distance
+    type Item = (usize, f32);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        while let Some(Reverse(entry)) = self.queue.pop()
+        {
+            match entry.item
+            {
+                QueueItem::Tile(index) => return Some((index, entry.distance)),
+                QueueItem::Node(node) =>
+                {
+                    match &self.index.nodes[node].kind
+                    {
+                        NodeKind::Leaf(indices) =>
+                        {
+                            indices.iter().for_each(|index|
+                            {
+                                self.queue.push(Reverse(QueueEntry{
+                                    distance: squared_distance(
+                                        &self.query,
+                                        self.index.point(*index)
+                                    ),
+                                    item: QueueItem::Tile(*index)
+                                }));
+                            });
+                        },
+                        NodeKind::Split{left, right} =>
+                        {
+                            [*left, *right].into_iter().for_each(|child|
+                            {
+                                self.queue.push(Reverse(QueueEntry{
+                                    distance: self.index.nodes[child].distance(&self.query),
+                                    item: QueueItem::Node(child)
+                                }));
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use image::{Rgb, RgbImage};
+
+    use crate::colors::ColorSpace;
+
+
+    fn test_images() -> Vec<LabImage>
+    {
+        (0..50_u32).map(|i|
+        {
+            let image = RgbImage::from_fn(6, 6, |x, y|
+            {
+                Rgb::from([
+                    (i * 5) as u8,
+                    (x * 40 + i) as u8,
+                    ((y * 13 + i * 7) % 256) as u8
+                ])
+            });
+
+            LabImage::from_rgb(image, ColorSpace::Cielab)
+        }).collect()
+    }
+
+    #[test]
+    fn nearest_is_sorted_and_complete()
+    {
+        let images = test_images();
+        let index = TileIndex::new(images.iter());
+
+        let query = descriptor(images[17].pyramid());
+
+        let found = index.nearest(query).collect::<Vec<_>>();
+
+        assert_eq!(found.len(), images.len());
+        assert_eq!(found[0].0, 17);
+
+        found.windows(2).for_each(|pair| assert!(pair[0].1 <= pair[1].1));
+    }
+}
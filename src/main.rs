@@ -1,16 +1,52 @@
-use std::process;
+#![allow(clippy::suspicious_else_formatting)]
+
+use std::{
+    fs,
+    env,
+    process,
+    path::{Path, PathBuf}
+};
+
+use image::{DynamicImage, Frame, ImageFormat, ImageResult, Rgb, Rgba, RgbImage};
 
 pub use colors::Lab;
 pub use imager::LabImage;
 pub use collager::Vec2;
 
-use collager::Collager;
+use collager::{
+    Assignment,
+    Collage,
+    Collager,
+    ColorCorrection,
+    Grout,
+    Libraries,
+    Quadtree,
+    ReuseLimits,
+    Search
+};
 use imager::Imager;
-use config::Config;
+use config::{Config, RenderConfig};
+use pool::WorkerPool;
+use cache::TileCache;
+use grid::Grid;
+use blend::{BlendMode, Overlay};
+use colors::{ColorSpace, DistanceMetric};
+use manifest::Manifest;
 
 mod collager;
 mod imager;
 mod config;
+mod index;
+mod pool;
+mod assignment;
+mod cache;
+mod grid;
+mod blend;
+mod animation;
+mod manifest;
+mod html;
+mod svg;
+mod dzi;
 
 pub mod colors;
 
@@ -22,25 +58,489 @@ fn complain(message: &str) -> !
     process::exit(1)
 }
 
+// empty means the same as the background, transparent or none means no color
+fn gap_color(color: &str, background: Rgb<u8>) -> Option<Rgb<u8>>
+{
+    match color.to_lowercase().as_str()
+    {
+        "" => Some(background),
+        "transparent" | "none" => None,
+        color => Some(colors::parse_rgb(color).unwrap_or_else(|err| complain(&err)))
+    }
+}
+
+// formats without alpha get the transparent parts filled with the background
+fn save_image(image: DynamicImage, path: &Path, background: Rgb<u8>) -> ImageResult<()>
+{
+    let opaque = matches!(ImageFormat::from_path(path), Ok(ImageFormat::Jpeg | ImageFormat::Pnm));
+
+    if !(opaque && image.color().has_alpha())
+    {
+        return image.save(path);
+    }
+
+    let image = image.into_rgba8();
+
+    let flattened = RgbImage::from_fn(image.width(), image.height(), |x, y|
+    {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+
+        let alpha = a as f32 / 255.0;
+        let mix = |value: u8, background: u8|
+        {
+            (value as f32 * alpha + background as f32 * (1.0 - alpha)).round() as u8
+        };
+
+        Rgb([mix(r, background[0]), mix(g, background[1]), mix(b, background[2])])
+    });
+
+    flattened.save(path)
+}
+
+// files written next to the collage that describe it
+struct Exports
+{
+    manifest: Option<PathBuf>,
+    html: Option<PathBuf>,
+    svg: Option<PathBuf>
+}
+
+impl Exports
+{
+    fn save(self, manifest: &Manifest, output: &Path, svg_embed: bool, pool: WorkerPool)
+    {
+        if let Some(path) = self.manifest
+        {
+            manifest.save(&path)
+                .unwrap_or_else(|err| complain(&format!("error saving manifest: {err:?}")));
+        }
+
+        if let Some(path) = self.html
+        {
+            html::save(&path, output, manifest)
+                .unwrap_or_else(|err| complain(&format!("error saving html: {err:?}")));
+        }
+
+        if let Some(path) = self.svg
+        {
+            svg::save(&path, manifest, svg_embed, pool)
+                .unwrap_or_else(|err| complain(&format!("error saving svg: {err:?}")));
+        }
+    }
+}
+
+fn save_dzi(path: &Path, manifest: &Manifest, pool: WorkerPool)
+{
+    dzi::save(path, manifest, pool)
+        .unwrap_or_else(|err| complain(&format!("error saving tile pyramid: {err:?}")));
+}
+
+fn render(config: RenderConfig)
+{
+    let path = Path::new(&config.manifest);
+
+    let is_csv = path.extension().map(|extension| extension.eq_ignore_ascii_case("csv"))
+        .unwrap_or(false);
+
+    if is_csv
+    {
+        complain("only json manifests can be rendered");
+    }
+
+    let manifest = Manifest::load(path)
+        .unwrap_or_else(|err| complain(&format!("error opening manifest: {err:?}")));
+
+    let background = colors::parse_rgb(&manifest.background)
+        .unwrap_or_else(|err| complain(&err));
+
+    let tile_size = Vec2{x: manifest.tile_width, y: manifest.tile_height};
+    let stored_size = Vec2{x: manifest.render_width, y: manifest.render_height};
+
+    let render_size = config.render_size.map(|width|
+    {
+        let aspect = stored_size.y as f64 / stored_size.x.max(1) as f64;
+
+        Vec2{x: width, y: ((width as f64 * aspect).round() as u32).max(1)}
+    }).unwrap_or(stored_size);
+
+    let gap_color = match config.gap_color.as_ref()
+    {
+        Some(color) => gap_color(color, background),
+        None => manifest.gap_color.as_ref().map(|color|
+        {
+            colors::parse_rgb(color).unwrap_or_else(|err| complain(&err))
+        })
+    };
+
+    let grout = Grout{
+        gap: config.gap.unwrap_or(manifest.gap),
+        margin: config.margin.unwrap_or(manifest.margin),
+        radius: config.corner_radius.unwrap_or(manifest.corner_radius),
+        color: gap_color
+    };
+
+    // stored positions are only right for the spacing they were made with
+    let relayout = render_size != stored_size
+        || grout.gap != manifest.gap
+        || grout.margin != manifest.margin;
+
+    let overlay = match config.overlay
+    {
+        Some(opacity) => (opacity > 0.0).then_some(Overlay{
+            opacity: opacity.min(1.0),
+            mode: config.overlay_mode.unwrap_or(BlendMode::Normal),
+            linear: config.overlay_linear
+        }),
+        // the stored overlay with only the mode or the linear blending changed
+        None => manifest.overlay.map(|overlay|
+        {
+            Overlay{
+                mode: config.overlay_mode.unwrap_or(overlay.mode),
+                linear: overlay.linear || config.overlay_linear,
+                ..overlay
+            }
+        })
+    };
+
+    if (config.overlay_mode.is_some() || config.overlay_linear) && overlay.is_none()
+    {
+        complain("--overlay-mode and --overlay-linear need an overlay to change");
+    }
+
+    let needs_target = overlay.is_some() || manifest.correction.is_some();
+
+    // the target only decides the grid shape otherwise, so it can be gone
+    let target = manifest.target.as_ref().and_then(|path|
+    {
+        match image::open(path)
+        {
+            Ok(image) => Some(image),
+            Err(err) if needs_target =>
+            {
+                complain(&format!("error opening target {}: {err:?}", path.display()))
+            },
+            Err(_) => None
+        }
+    });
+
+    if needs_target && target.is_none()
+    {
+        complain("the overlay and the color correction need the target the manifest was made from");
+    }
+
+    if config.dzi.is_some() && (needs_target || config.html.is_some())
+    {
+        complain("--dzi doesnt work with overlays, color correction or --html");
+    }
+
+    // without the target the grid decides its own shape
+    let target = target.map(DynamicImage::into_rgb8).unwrap_or_else(||
+    {
+        let size = manifest.grid.size(manifest.columns, manifest.rows, tile_size);
+
+        RgbImage::new(size.x, size.y)
+    });
+
+    let pool = WorkerPool::new(config.jobs);
+
+    let collager_config = collager::Config{
+        width: Some(manifest.columns),
+        height: Some(manifest.rows),
+        tiles_total: None,
+        fit: manifest.fit,
+        background,
+        grout,
+        tile_size,
+        grid: manifest.grid,
+        metric: DistanceMetric::Cie76,
+        space: config.space.unwrap_or(manifest.space),
+        search: Search::Linear,
+        reuse: None,
+        assignment: Assignment::Greedy,
+        correction: manifest.correction,
+        render_size: Some(render_size),
+        quadtree: None,
+        overlay,
+        diffusion: None,
+        switch_cost: None,
+        pool,
+        manifest: false,
+        target_path: manifest.target.clone()
+    };
+
+    let collager = Collager::new(target, collager_config);
+
+    if collager.cells() != (manifest.columns, manifest.rows)
+    {
+        complain("the target image doesnt fit the grid in the manifest anymore");
+    }
+
+    collager.check_manifest(&manifest, relayout)
+        .unwrap_or_else(|err| complain(&format!("bad manifest: {err}")));
+
+    // the pyramid gets drawn a piece at a time straight from the tile files
+    if let Some(path) = config.dzi
+    {
+        let manifest = collager.relaid_manifest(&manifest, relayout);
+
+        let exports = Exports{manifest: None, html: None, svg: config.svg};
+        exports.save(&manifest, &path, config.svg_embed, pool);
+
+        save_dzi(&path, &manifest, pool);
+
+        return;
+    }
+
+    let Collage{image, manifest, ..} = collager.render_manifest(&manifest, relayout)
+        .unwrap_or_else(|err| complain(&format!("error rendering collage: {err:?}")));
+
+    save_image(image, Path::new(&config.output), background)
+        .unwrap_or_else(|err| complain(&format!("error saving collage: {err:?}")));
+
+    if let Some(manifest) = manifest
+    {
+        let exports = Exports{manifest: None, html: config.html, svg: config.svg};
+
+        exports.save(&manifest, Path::new(&config.output), config.svg_embed, pool);
+    }
+}
+
 fn main()
 {
+    let mut args = env::args().collect::<Vec<_>>();
+
+    if args.get(1).map(String::as_str) == Some("render")
+    {
+        let subcommand = args.remove(1);
+        args[0] = format!("{} {subcommand}", args[0]);
+
+        render(RenderConfig::parse(args));
+
+        return;
+    }
+
     let config = Config::parse();
 
-    let image = image::open(config.input)
-        .unwrap_or_else(|err| complain(&format!("error opening image: {err:?}")));
+    let input = Path::new(&config.input);
+
+    // a directory of numbered frames, every one gets opened when its collaged
+    let sequence = input.is_dir().then(||
+    {
+        animation::numbered_frames(input)
+            .unwrap_or_else(|err| complain(&format!("error opening frames: {err:?}")))
+    });
+
+    let frames = if sequence.is_some()
+    {
+        None
+    } else
+    {
+        animation::load_frames(input)
+            .unwrap_or_else(|err| complain(&format!("error opening image: {err:?}")))
+    };
+
+    // animations dont need the still image
+    let image = (sequence.is_none() && frames.is_none()).then(||
+    {
+        image::open(input).unwrap_or_else(|err| complain(&format!("error opening image: {err:?}")))
+    });
+
+    let exports = Exports{
+        manifest: config.manifest.clone(),
+        html: config.html.clone(),
+        svg: config.svg.clone()
+    };
+
+    let describe = exports.manifest.is_some() || exports.html.is_some() || exports.svg.is_some();
+
+    if describe && (sequence.is_some() || frames.is_some())
+    {
+        complain("--manifest, --html and --svg only work with still images");
+    }
+
+    if config.dzi.is_some()
+    {
+        if sequence.is_some() || frames.is_some()
+        {
+            complain("--dzi only works with still images");
+        }
+
+        // the pyramid is drawn from the manifest which only knows the tiles
+        if config.overlay > 0.0 || config.correction > 0.0 || exports.html.is_some()
+        {
+            complain("--dzi doesnt work with --overlay, --correction or --html");
+        }
+    }
+
+    if frames.is_some() && !animation::can_save(Path::new(&config.output))
+    {
+        complain("animations can only be saved as gif or png");
+    }
+
+    // permuted images get made from the full files so theres nothing to cache
+    if config.cache.is_some() && config.depth > 0
+    {
+        complain("--cache doesnt work with --depth");
+    }
+
+    if config.cache.is_some() && config.no_cache
+    {
+        complain("--cache and --no-cache cant be used together");
+    }
+
+    let pool = WorkerPool::new(config.jobs);
+
+    if config.correct_deviation && config.correction <= 0.0
+    {
+        complain("--correct-contrast only works together with --correct");
+    }
+
+    let correction = (config.correction > 0.0).then_some(ColorCorrection{
+        strength: config.correction.min(1.0),
+        match_deviation: config.correct_deviation
+    });
+
+    let search = if config.index
+    {
+        Search::Indexed{candidates: (config.candidates != 0).then_some(config.candidates)}
+    } else
+    {
+        Search::Linear
+    };
+
+    let max_uses = (config.max_uses != 0).then_some(config.max_uses);
+    let min_distance = (config.min_repeat_distance > 0.0).then_some(config.min_repeat_distance);
+
+    let reuse = (max_uses.is_some() || min_distance.is_some()).then_some(ReuseLimits{
+        max_uses,
+        min_distance
+    });
+
+    if min_distance.is_some() && config.assignment != Assignment::Greedy
+    {
+        complain("--min-repeat-distance only works with greedy assignment");
+    }
+
+    let quadtree = (config.quadtree_min_size != 0).then_some(Quadtree{
+        min_size: config.quadtree_min_size,
+        threshold: config.split_error
+    });
+
+    if quadtree.is_some()
+    {
+        if config.grid != Grid::Square
+        {
+            complain("quadtree cells only work with the square grid");
+        }
+
+        if config.assignment != Assignment::Greedy || reuse.is_some()
+        {
+            complain("quadtree cells only work with greedy assignment without reuse limits");
+        }
+    }
+
+    let tile_size = Vec2{
+        x: config.pixel_size,
+        y: if config.tile_height == 0 { config.pixel_size } else { config.tile_height }
+    };
+
+    let render_size = (config.render_size != 0).then(||
+    {
+        let aspect = tile_size.y as f64 / tile_size.x as f64;
+
+        Vec2{
+            x: config.render_size,
+            y: ((config.render_size as f64 * aspect).round() as u32).max(1)
+        }
+    });
+
+    // the cie94 and ciede2000 constants are tuned for cielab and mean nothing in oklab
+    if config.space == ColorSpace::Oklab && config.metric != DistanceMetric::Cie76
+    {
+        complain("oklab only works with the cie76 metric");
+    }
+
+    // the index cant rule anything out for the other metrics, so exact search would check
+    // every image anyway and be slower than going through them in order
+    if config.index && config.candidates == 0 && config.metric != DistanceMetric::Cie76
+    {
+        complain("exact --index search only works with the cie76 metric, use --candidates");
+    }
+
+    if config.tiles_total.is_some() && (config.width.is_some() || config.height.is_some())
+    {
+        complain("--tiles-total cant be used together with --width or --height");
+    }
+
+    let background = colors::parse_rgb(&config.background)
+        .unwrap_or_else(|err| complain(&err));
+
+    let overlay = (config.overlay > 0.0).then_some(Overlay{
+        opacity: config.overlay.min(1.0),
+        mode: config.overlay_mode,
+        linear: config.overlay_linear
+    });
+
+    let grout = Grout{
+        gap: config.gap,
+        margin: config.margin,
+        radius: config.corner_radius,
+        color: gap_color(&config.gap_color, background)
+    };
+
+    let diffusion = (config.diffusion > 0.0).then_some(config.diffusion.min(1.0));
 
-    let collager = Collager::new(
-        image.into_rgb8(),
-        config.width,
-        config.pixel_size,
-        config.output_indices
-    );
+    let switch_cost = (config.switch_cost > 0.0).then_some(config.switch_cost);
+
+    if switch_cost.is_some() && quadtree.is_some()
+    {
+        complain("--switch-cost doesnt work with quadtree cells");
+    }
+
+    if diffusion.is_some() && (config.assignment != Assignment::Greedy || quadtree.is_some())
+    {
+        complain("error diffusion only works with greedy assignment and without quadtree cells");
+    }
+
+    let collager_config = collager::Config{
+        width: config.width,
+        height: config.height,
+        tiles_total: config.tiles_total,
+        fit: config.fit,
+        background,
+        grout,
+        tile_size,
+        grid: config.grid,
+        metric: config.metric,
+        space: config.space,
+        search,
+        reuse,
+        assignment: config.assignment,
+        correction,
+        render_size,
+        quadtree,
+        overlay,
+        diffusion,
+        switch_cost,
+        pool,
+        manifest: describe,
+        target_path: Some(input.to_owned())
+    };
 
     let imager_config = imager::Config{
-        image_size: config.pixel_size,
+        image_size: tile_size,
         allow_rotate: config.allow_rotate,
         allow_invert: config.allow_invert,
-        depth: config.depth
+        depth: config.depth,
+        space: config.space,
+        cache: (!config.no_cache && config.depth == 0).then(||
+        {
+            config.cache.clone().unwrap_or_else(||
+            {
+                TileCache::default_path(Path::new(&config.directory))
+            })
+        }),
+        pool
     };
 
     let imager = Imager::new(config.directory, imager_config)
@@ -51,7 +551,87 @@ fn main()
         imager.save("output/");
     }
 
-    let collage = collager.collage(imager.images());
+    // tiles of the last frame so the next one can keep them
+    let mut previous: Option<Vec<usize>> = None;
+
+    // only the target changes between frames so the libraries get built once
+    let mut libraries: Option<Libraries> = None;
+
+    let mut collage = |image: DynamicImage|
+    {
+        let mut collager = Collager::new(image.into_rgb8(), collager_config.clone());
+
+        if let Some(indices) = previous.as_ref()
+        {
+            collager.set_previous(indices);
+        }
 
-    collage.save(config.output).unwrap();
+        let libraries = libraries.get_or_insert_with(|| collager.libraries(&imager));
+
+        let collage = collager.collage(&imager, libraries)
+            .unwrap_or_else(|err| complain(&format!("error rendering collage: {err:?}")));
+
+        previous = Some(collage.indices.clone());
+
+        collage
+    };
+
+    if let Some(paths) = sequence
+    {
+        let output = Path::new(&config.output);
+
+        fs::create_dir_all(output)
+            .unwrap_or_else(|err| complain(&format!("error creating output directory: {err:?}")));
+
+        paths.into_iter().for_each(|path|
+        {
+            let image = image::open(&path)
+                .unwrap_or_else(|err| complain(&format!("error opening frame: {err:?}")));
+
+            let name = path.file_name().expect("frames must be files");
+
+            save_image(collage(image).image, &output.join(name), background)
+                .unwrap_or_else(|err| complain(&format!("error saving frame: {err:?}")));
+        });
+    } else if let Some(frames) = frames
+    {
+        let collages = frames.into_iter().map(|frame|
+        {
+            let delay = frame.delay();
+            let collage = collage(DynamicImage::from(frame.into_buffer()));
+
+            Frame::from_parts(collage.image.into_rgba8(), 0, 0, delay)
+        }).collect();
+
+        animation::save_frames(Path::new(&config.output), collages)
+            .unwrap_or_else(|err| complain(&format!("error saving animation: {err:?}")));
+    } else
+    {
+        let image = image.expect("still images must be opened");
+
+        if let Some(path) = config.dzi.as_ref()
+        {
+            let collager = Collager::new(image.into_rgb8(), collager_config.clone());
+
+            let manifest = collager.describe(&imager, &collager.libraries(&imager));
+
+            exports.save(&manifest, path, config.svg_embed, pool);
+
+            save_dzi(path, &manifest, pool);
+
+            return;
+        }
+
+        let Collage{image, manifest, ..} = collage(image);
+
+        let output = Path::new(&config.output);
+
+        save_image(image, output, background)
+            .unwrap_or_else(|err| complain(&format!("error saving collage: {err:?}")));
+
+        if let Some(manifest) = manifest
+        {
+            exports.save(&manifest, output, config.svg_embed, pool);
+        }
+    }
 }
@@ -0,0 +1,424 @@
+use std::{
+    fs,
+    io,
+    env,
+    path::{Path, PathBuf, Component}
+};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    collager::{FitMode, ColorCorrection},
+    colors::ColorSpace,
+    blend::Overlay,
+    grid::Grid,
+    imager::{Transform, TileSource}
+};
+
+
+// url to path from a file in directory, relative if they share anything
+pub fn link(path: &Path, directory: &Path) -> String
+{
+    let (relative, parts) = path_parts(path, directory);
+
+    let encoded = parts.iter().map(|part| percent_encode(part)).collect::<Vec<_>>().join("/");
+
+    if relative
+    {
+        encoded
+    } else
+    {
+        format!("file:///{encoded}")
+    }
+}
+
+// path from a file in directory, relative if they share anything and absolute otherwise
+pub fn relative_path(path: &Path, directory: &Path) -> PathBuf
+{
+    let (relative, parts) = path_parts(path, directory);
+
+    if relative
+    {
+        parts.into_iter().collect()
+    } else
+    {
+        absolute(path)
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf
+{
+    // an empty parent is the current directory
+    let path = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+
+    fs::canonicalize(path).or_else(|_| env::current_dir().map(|current| current.join(path)))
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+// whether path can be reached relatively from directory, and the parts of that path or
+// of the absolute one without its root
+fn path_parts(path: &Path, directory: &Path) -> (bool, Vec<String>)
+{
+    let (path, directory) = (absolute(path), absolute(directory));
+
+    let components = path.components().collect::<Vec<_>>();
+    let base = directory.components().collect::<Vec<_>>();
+
+    let shared = components.iter().zip(&base).take_while(|(a, b)| a == b).count();
+
+    // only the root is shared, might as well be absolute
+    let relative = shared > 1 || (shared == 1 && !path.has_root());
+
+    let parts = if relative
+    {
+        let parents = base[shared..].iter().map(|_| "..".to_owned());
+
+        parents.chain(components[shared..].iter().map(|component|
+        {
+            component.as_os_str().to_string_lossy().into_owned()
+        })).collect::<Vec<_>>()
+    } else
+    {
+        components.iter().filter_map(|component|
+        {
+            match component
+            {
+                Component::RootDir | Component::Prefix(_) => None,
+                component => Some(component.as_os_str().to_string_lossy().into_owned())
+            }
+        }).collect()
+    };
+
+    (relative, parts)
+}
+
+// safe to put in xml text and quoted attributes, newlines turn into references so
+// attributes like titles keep them
+pub fn escape(s: &str) -> String
+{
+    s.chars().map(|c|
+    {
+        match c
+        {
+            '&' => "&amp;".to_owned(),
+            '<' => "&lt;".to_owned(),
+            '>' => "&gt;".to_owned(),
+            '"' => "&quot;".to_owned(),
+            '\'' => "&#39;".to_owned(),
+            '\n' => "&#10;".to_owned(),
+            c => c.to_string()
+        }
+    }).collect()
+}
+
+fn percent_encode(s: &str) -> String
+{
+    s.bytes().map(|byte|
+    {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte)
+        {
+            (byte as char).to_string()
+        } else
+        {
+            format!("%{byte:02X}")
+        }
+    }).collect()
+}
+
+// everything about a finished collage, enough to draw it again without matching
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest
+{
+    // the image that got collaged
+    pub target: Option<PathBuf>,
+    pub grid: Grid,
+    pub columns: u32,
+    pub rows: u32,
+    pub fit: FitMode,
+    // rrggbb
+    pub background: String,
+    // the cell size the target got matched at
+    pub tile_width: u32,
+    pub tile_height: u32,
+    // the cell size in the output
+    pub render_width: u32,
+    pub render_height: u32,
+    // size of the whole output image
+    pub width: u32,
+    pub height: u32,
+    pub gap: u32,
+    pub margin: u32,
+    pub corner_radius: u32,
+    // rrggbb, none if the grout is transparent
+    pub gap_color: Option<String>,
+    // what the correction and the overlay worked in
+    pub space: ColorSpace,
+    pub correction: Option<ColorCorrection>,
+    pub overlay: Option<Overlay>,
+    pub cells: Vec<ManifestCell>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestCell
+{
+    pub column: u32,
+    pub row: u32,
+    // how many times the cell got split by the quadtree
+    pub level: usize,
+    // the part of the target the tile was matched against
+    pub target_x: u32,
+    pub target_y: u32,
+    pub target_width: u32,
+    pub target_height: u32,
+    // where the tile is in the output image
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub source: PathBuf,
+    pub transform: Transform,
+    // transparent images blended on top, bottom first
+    pub overlays: Vec<ManifestLayer>,
+    // average color distance per pixel between the tile and the target
+    pub error: f32
+}
+
+impl ManifestCell
+{
+    pub fn tile_source(&self) -> TileSource
+    {
+        TileSource{
+            path: self.source.clone(),
+            transform: self.transform,
+            overlays: self.overlays.iter().map(|layer| (layer.path.clone(), layer.transform))
+                .collect()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestLayer
+{
+    pub path: PathBuf,
+    pub transform: Transform
+}
+
+impl Manifest
+{
+    // only json manifests can be read back, relative paths are from the manifests directory
+    pub fn load(path: &Path) -> io::Result<Self>
+    {
+        let contents = fs::read_to_string(path)?;
+
+        let manifest: Self = serde_json::from_str(&contents).map_err(io::Error::other)?;
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        Ok(manifest.map_paths(|path| directory.join(path)))
+    }
+
+    // csv if the extension says so, json otherwise, the paths are written relative to
+    // the manifest so the whole folder can be moved around
+    pub fn save(&self, path: &Path) -> io::Result<()>
+    {
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let manifest = self.clone().map_paths(|path| relative_path(path, directory));
+
+        manifest.save_as_is(path)
+    }
+
+    fn map_paths(mut self, f: impl Fn(&Path) -> PathBuf) -> Self
+    {
+        self.target = self.target.as_deref().map(&f);
+
+        self.cells.iter_mut().for_each(|cell|
+        {
+            cell.source = f(&cell.source);
+
+            cell.overlays.iter_mut().for_each(|layer| layer.path = f(&layer.path));
+        });
+
+        self
+    }
+
+    fn save_as_is(&self, path: &Path) -> io::Result<()>
+    {
+        let is_csv = path.extension().map(|extension|
+        {
+            extension.eq_ignore_ascii_case("csv")
+        }).unwrap_or(false);
+
+        let contents = if is_csv
+        {
+            self.to_csv()
+        } else
+        {
+            serde_json::to_string_pretty(self).map_err(io::Error::other)?
+        };
+
+        fs::write(path, contents)
+    }
+
+    // one row per cell, the overlays are a json array since their paths can have anything in them
+    fn to_csv(&self) -> String
+    {
+        let mut s = String::from(
+            "column,row,level,target_x,target_y,target_width,target_height,\
+            x,y,width,height,source,rotation,mirrored,inverted,overlays,error\n"
+        );
+
+        self.cells.iter().for_each(|cell|
+        {
+            let Transform{rotation, mirrored, inverted} = cell.transform;
+
+            let overlays = cell.overlays.iter().map(|layer|
+            {
+                let Transform{rotation, mirrored, inverted} = layer.transform;
+
+                serde_json::json!({
+                    "path": layer.path.display().to_string(),
+                    "rotation": rotation,
+                    "mirrored": mirrored,
+                    "inverted": inverted
+                })
+            }).collect::<serde_json::Value>().to_string();
+
+            let fields = [
+                cell.column.to_string(),
+                cell.row.to_string(),
+                cell.level.to_string(),
+                cell.target_x.to_string(),
+                cell.target_y.to_string(),
+                cell.target_width.to_string(),
+                cell.target_height.to_string(),
+                cell.x.to_string(),
+                cell.y.to_string(),
+                cell.width.to_string(),
+                cell.height.to_string(),
+                Self::csv_field(&cell.source.display().to_string()),
+                rotation.to_string(),
+                mirrored.to_string(),
+                inverted.to_string(),
+                Self::csv_field(&overlays),
+                cell.error.to_string()
+            ];
+
+            s += &fields.join(",");
+            s.push('\n');
+        });
+
+        s
+    }
+
+    fn csv_field(value: &str) -> String
+    {
+        if value.contains([',', '"', '\n', '\r'])
+        {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else
+        {
+            value.to_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn csv_quoting()
+    {
+        let cell = ManifestCell{
+            column: 1,
+            row: 2,
+            level: 0,
+            target_x: 8,
+            target_y: 16,
+            target_width: 8,
+            target_height: 8,
+            x: 32,
+            y: 64,
+            width: 32,
+            height: 32,
+            source: PathBuf::from("tiles/a, \"b\" c.png"),
+            transform: Transform{rotation: 1, mirrored: true, inverted: false},
+            overlays: vec![
+                ManifestLayer{path: PathBuf::from("x.png"), transform: Transform::default()},
+                ManifestLayer{
+                    path: PathBuf::from("y;z|1.png"),
+                    transform: Transform{rotation: 2, mirrored: false, inverted: true}
+                }
+            ],
+            error: 2.5
+        };
+
+        let manifest = Manifest{
+            target: None,
+            grid: Grid::Square,
+            columns: 2,
+            rows: 3,
+            fit: FitMode::Fit,
+            background: "000000".to_owned(),
+            tile_width: 8,
+            tile_height: 8,
+            render_width: 32,
+            render_height: 32,
+            width: 64,
+            height: 96,
+            gap: 0,
+            margin: 0,
+            corner_radius: 0,
+            gap_color: Some("000000".to_owned()),
+            space: ColorSpace::Cielab,
+            correction: None,
+            overlay: None,
+            cells: vec![cell]
+        };
+
+        let csv = manifest.to_csv();
+        let row = csv.lines().nth(1).unwrap();
+
+        let source = "\"tiles/a, \"\"b\"\" c.png\"";
+        let overlays = "\"[{\"\"inverted\"\":false,\"\"mirrored\"\":false,\
+            \"\"path\"\":\"\"x.png\"\",\"\"rotation\"\":0},{\"\"inverted\"\":true,\
+            \"\"mirrored\"\":false,\"\"path\"\":\"\"y;z|1.png\"\",\"\"rotation\"\":2}]\"";
+
+        assert_eq!(row, format!("1,2,0,8,16,8,8,32,64,32,32,{source},1,true,false,{overlays},2.5"));
+
+        // the overlays column reads back as json once its unquoted
+        let column = &overlays[1..overlays.len() - 1].replace("\"\"", "\"");
+        let layers: serde_json::Value = serde_json::from_str(column).unwrap();
+
+        assert_eq!(layers[1]["path"], "y;z|1.png");
+        assert_eq!(layers[1]["rotation"], 2);
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        let loaded: Manifest = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.cells[0].source, manifest.cells[0].source);
+        assert_eq!(loaded.cells[0].transform, manifest.cells[0].transform);
+    }
+
+    #[test]
+    fn links()
+    {
+        let link = |path: &str, directory: &str| link(Path::new(path), Path::new(directory));
+
+        assert_eq!(link("/tmp/a/tiles/img 1.png", "/tmp/a/out"), "../tiles/img%201.png");
+        assert_eq!(link("/tmp/a/b.png", "/tmp/a"), "b.png");
+        assert_eq!(link("/x/y#.png", "/tmp"), "file:///x/y%23.png");
+
+        assert_eq!(escape("a <b> & \"c\"\nd'"), "a &lt;b&gt; &amp; &quot;c&quot;&#10;d&#39;");
+
+        let relative = |path: &str, directory: &str|
+        {
+            relative_path(Path::new(path), Path::new(directory))
+        };
+
+        assert_eq!(relative("/tmp/a/tiles/1.png", "/tmp/a/out"), PathBuf::from("../tiles/1.png"));
+        assert_eq!(relative("/x/y.png", "/tmp"), PathBuf::from("/x/y.png"));
+    }
+}
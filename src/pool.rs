@@ -0,0 +1,93 @@
+use std::{
+    thread,
+    sync::Mutex,
+    num::NonZeroUsize
+};
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerPool
+{
+    jobs: usize
+}
+
+impl WorkerPool
+{
+    // 0 jobs picks however many threads the machine can run at once
+    pub fn new(jobs: usize) -> Self
+    {
+        let jobs = if jobs == 0
+        {
+            thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1)
+        } else
+        {
+            jobs
+        };
+
+        Self{jobs}
+    }
+
+    pub fn jobs(&self) -> usize
+    {
+        self.jobs
+    }
+
+    // runs f on every item with at most jobs threads, keeps the order of the items
+    pub fn map<T, R, F>(&self, items: impl IntoIterator<Item=T>, f: F) -> Vec<R>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(T) -> R + Sync
+    {
+        let items = items.into_iter().collect::<Vec<_>>();
+        let amount = items.len();
+
+        let queue = Mutex::new(items.into_iter().enumerate());
+
+        let mut results = thread::scope(|scope|
+        {
+            let handles = (0..self.jobs.min(amount)).map(|_|
+            {
+                scope.spawn(||
+                {
+                    let mut results = Vec::new();
+
+                    loop
+                    {
+                        // the lock is dropped before doing any work
+                        let item = queue.lock().unwrap().next();
+
+                        let Some((index, item)) = item else { break };
+
+                        results.push((index, f(item)));
+                    }
+
+                    results
+                })
+            }).collect::<Vec<_>>();
+
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        });
+
+        results.sort_unstable_by_key(|(index, _)| *index);
+
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn keeps_order()
+    {
+        let pool = WorkerPool::new(3);
+
+        let squares = pool.map(0..100_u64, |x| x * x);
+
+        assert_eq!(squares, (0..100_u64).map(|x| x * x).collect::<Vec<_>>());
+        assert!(pool.map(Vec::<u64>::new(), |x| x).is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    collections::HashMap
+};
+
+use image::ImageOutputFormat;
+
+use crate::{
+    Vec2,
+    grid::Grid,
+    imager::{self, Imager, Transform},
+    manifest::{self, Manifest},
+    pool::WorkerPool
+};
+
+
+// one image element per tile, referencing the original files or with them embedded as pngs
+pub fn save(
+    path: &Path,
+    manifest: &Manifest,
+    embed: bool,
+    pool: WorkerPool
+) -> Result<(), imager::Error>
+{
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    // every file at every size it gets drawn at only has to be in there once
+    let mut images: Vec<(PathBuf, Vec2)> = Vec::new();
+    let mut ids: HashMap<(PathBuf, Vec2), usize> = HashMap::new();
+
+    let mut image_id = |path: &Path, size: Vec2|
+    {
+        *ids.entry((path.to_owned(), size)).or_insert_with_key(|key|
+        {
+            images.push(key.clone());
+
+            images.len() - 1
+        })
+    };
+
+    let mut clips: Vec<Vec2> = Vec::new();
+
+    let clipped = manifest.grid != Grid::Square || manifest.corner_radius > 0;
+
+    let cells = manifest.cells.iter().map(|cell|
+    {
+        let size = Vec2{x: cell.width.max(1), y: cell.height.max(1)};
+
+        let layers = [(cell.source.as_path(), cell.transform)].into_iter()
+            .chain(cell.overlays.iter().map(|layer| (layer.path.as_path(), layer.transform)))
+            .map(|(path, transform)|
+            {
+                let id = image_id(path, unrotated(size, transform));
+
+                format!("{}\n", use_image(id, size, transform))
+            }).collect::<String>();
+
+        let clip = if clipped
+        {
+            if !clips.contains(&size)
+            {
+                clips.push(size);
+            }
+
+            format!(" clip-path=\"url(#clip-{}-{})\"", size.x, size.y)
+        } else
+        {
+            String::new()
+        };
+
+        format!("<g transform=\"translate({} {})\"{clip}>\n{layers}</g>\n", cell.x, cell.y)
+    }).collect::<String>();
+
+    let hrefs = if embed
+    {
+        pool.map(images.iter(), |(path, size)| embedded(path, *size))
+            .into_iter().collect::<Result<Vec<_>, _>>()?
+    } else
+    {
+        images.iter().map(|(path, _)| manifest::link(path, directory)).collect()
+    };
+
+    let definitions = images.iter().zip(hrefs).enumerate().map(|(id, ((_, size), href))|
+    {
+        format!(
+            "<image id=\"tile-{id}\" width=\"{}\" height=\"{}\" \
+            preserveAspectRatio=\"xMidYMid slice\" xlink:href=\"{}\"/>\n",
+            size.x,
+            size.y,
+            manifest::escape(&href)
+        )
+    }).collect::<String>();
+
+    let clip_paths = clips.into_iter().map(|size|
+    {
+        format!(
+            "<clipPath id=\"clip-{}-{}\">{}</clipPath>\n",
+            size.x,
+            size.y,
+            clip_shape(manifest, size)
+        )
+    }).collect::<String>();
+
+    let background = manifest.gap_color.as_ref().map(|color|
+    {
+        format!("<rect width=\"100%\" height=\"100%\" fill=\"#{}\"/>\n", manifest::escape(color))
+    }).unwrap_or_default();
+
+    let (width, height) = (manifest.width, manifest.height);
+
+    let svg = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">
+<defs>
+<filter id=\"invert\" color-interpolation-filters=\"sRGB\">
+<feComponentTransfer>
+<feFuncR type=\"table\" tableValues=\"1 0\"/>
+<feFuncG type=\"table\" tableValues=\"1 0\"/>
+<feFuncB type=\"table\" tableValues=\"1 0\"/>
+</feComponentTransfer>
+</filter>
+{clip_paths}{definitions}</defs>
+{background}{cells}</svg>
+"
+    );
+
+    fs::write(path, svg)?;
+
+    Ok(())
+}
+
+// the size the image has before its turned into one that fills size
+fn unrotated(size: Vec2, transform: Transform) -> Vec2
+{
+    if transform.rotation % 2 == 1
+    {
+        Vec2{x: size.y, y: size.x}
+    } else
+    {
+        size
+    }
+}
+
+// mirrors, turns around the center and inverts the same way Transform::apply does
+fn use_image(id: usize, size: Vec2, transform: Transform) -> String
+{
+    let image_size = unrotated(size, transform);
+
+    let mut steps = vec![format!("translate({} {})", size.x as f32 / 2.0, size.y as f32 / 2.0)];
+
+    if !transform.rotation.is_multiple_of(4)
+    {
+        steps.push(format!("rotate({})", (transform.rotation % 4) as u32 * 90));
+    }
+
+    if transform.mirrored
+    {
+        steps.push("scale(-1 1)".to_owned());
+    }
+
+    steps.push(format!(
+        "translate({} {})",
+        -(image_size.x as f32) / 2.0,
+        -(image_size.y as f32) / 2.0
+    ));
+
+    let filter = if transform.inverted { " filter=\"url(#invert)\"" } else { "" };
+
+    format!("<use xlink:href=\"#tile-{id}\" transform=\"{}\"{filter}/>", steps.join(" "))
+}
+
+fn clip_shape(manifest: &Manifest, size: Vec2) -> String
+{
+    let (width, height) = (size.x as f32, size.y as f32);
+
+    let polygon = |points: [(f32, f32); 6]|
+    {
+        let points = points.map(|(x, y)| format!("{} {}", x * width, y * height));
+
+        format!("<polygon points=\"{}\"/>", points.join(" "))
+    };
+
+    match manifest.grid
+    {
+        Grid::HexPointy => polygon(
+            [(0.5, 0.0), (1.0, 0.25), (1.0, 0.75), (0.5, 1.0), (0.0, 0.75), (0.0, 0.25)]
+        ),
+        Grid::HexFlat => polygon(
+            [(0.25, 0.0), (0.75, 0.0), (1.0, 0.5), (0.75, 1.0), (0.25, 1.0), (0.0, 0.5)]
+        ),
+        Grid::Square =>
+        {
+            let radius = manifest.corner_radius.min(size.x / 2).min(size.y / 2);
+
+            format!("<rect width=\"{}\" height=\"{}\" rx=\"{radius}\"/>", size.x, size.y)
+        }
+    }
+}
+
+// the file filled into size as a png data uri
+fn embedded(path: &Path, size: Vec2) -> Result<String, imager::Error>
+{
+    let image = image::open(path).map_err(|err| imager::Error::new(path, err))?;
+
+    let mut png = Cursor::new(Vec::new());
+
+    Imager::resize_image(image, size).write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|err| imager::Error::new(path, err))?;
+
+    Ok(format!("data:image/png;base64,{}", base64(png.get_ref())))
+}
+
+fn base64(bytes: &[u8]) -> String
+{
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    bytes.chunks(3).flat_map(|chunk|
+    {
+        let value = chunk.iter().enumerate().fold(0_u32, |acc, (index, byte)|
+        {
+            acc | ((*byte as u32) << (16 - index * 8))
+        });
+
+        // one more character than there are bytes, the rest is padding
+        (0..4).map(move |index|
+        {
+            if index <= chunk.len()
+            {
+                ALPHABET[((value >> (18 - index * 6)) & 0x3f) as usize] as char
+            } else
+            {
+                '='
+            }
+        })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn encoding()
+    {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+
+        let size = Vec2{x: 20, y: 10};
+        let turned = Transform{rotation: 1, mirrored: true, inverted: true};
+
+        assert_eq!(
+            use_image(3, size, turned),
+            "<use xlink:href=\"#tile-3\" transform=\"translate(10 5) rotate(90) scale(-1 1) \
+            translate(-5 -10)\" filter=\"url(#invert)\"/>"
+        );
+    }
+}